@@ -0,0 +1,289 @@
+use std::error::Error;
+use std::fs::{File, create_dir_all};
+use std::io::{BufWriter, Write, stdout};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, RgbaImage};
+use log::info;
+
+use crate::camera_path::CameraPath;
+use crate::world::World;
+
+/// Trades a little GIF palette quality for much faster encoding than the
+/// slowest, best setting of 1.
+const GIF_SPEED: i32 = 10;
+
+/// How far above the scene turntable animations look down from, in radians.
+const TURNTABLE_ELEVATION: f32 = 0.3;
+
+/// Write `frames`, each `width` by `height` RGBA pixels, to `path` as an
+/// endlessly looping animation showing each frame for `delay`. The format
+/// follows the extension: `.gif`, or `.png` and `.apng` for an animated PNG.
+pub fn write_animation(
+    path: &Path,
+    width: usize,
+    height: usize,
+    frames: &[Vec<u8>],
+    delay: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    let write = match extension.as_deref() {
+        Some("gif") => write_gif,
+        Some("png") | Some("apng") => write_apng,
+        _ => {
+            return Err(
+                format!("Unknown animation format, expected .gif or .png: {path:?}").into(),
+            );
+        }
+    };
+    write(
+        BufWriter::new(File::create(path)?),
+        width,
+        height,
+        frames,
+        delay,
+    )
+}
+
+fn write_gif(
+    file: BufWriter<File>,
+    width: usize,
+    height: usize,
+    frames: &[Vec<u8>],
+    delay: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let mut encoder = GifEncoder::new_with_speed(file, GIF_SPEED);
+    encoder.set_repeat(Repeat::Infinite)?;
+    for frame in frames {
+        let image = RgbaImage::from_raw(width as u32, height as u32, frame.clone())
+            .ok_or("Frame doesn't match the animation size")?;
+        encoder.encode_frame(Frame::from_parts(
+            image,
+            0,
+            0,
+            Delay::from_saturating_duration(delay),
+        ))?;
+    }
+    Ok(())
+}
+
+/// `image` only decodes animated PNGs, so they're written with `png` directly.
+fn write_apng(
+    file: BufWriter<File>,
+    width: usize,
+    height: usize,
+    frames: &[Vec<u8>],
+    delay: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    // 0 plays forever.
+    encoder.set_animated(frames.len() as u32, 0)?;
+    encoder.set_frame_delay(delay.as_millis().min(u16::MAX as u128) as u16, 1000)?;
+    let mut writer = encoder.write_header()?;
+    for frame in frames {
+        writer.write_image_data(frame)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Where the frames of a long render go one at a time as they're drawn, so
+/// they never all have to be in memory.
+pub enum FrameWriter {
+    /// Numbered PNGs in a directory.
+    Pngs {
+        dir: PathBuf,
+        width: usize,
+        height: usize,
+        next: usize,
+    },
+    /// Uncompressed YUV4MPEG2 video, which encoders like ffmpeg read as is.
+    Y4m {
+        out: BufWriter<Box<dyn Write>>,
+        width: usize,
+        height: usize,
+    },
+}
+
+impl FrameWriter {
+    /// Frames of `width` by `height` shown `fps` times a second. `path` is a
+    /// `.y4m` file, `-` for Y4M on stdout, or else a directory for the PNGs,
+    /// created if needed.
+    pub fn create(
+        path: &str,
+        width: usize,
+        height: usize,
+        fps: f32,
+    ) -> Result<Self, Box<dyn Error>> {
+        let is_y4m = path == "-"
+            || Path::new(path)
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("y4m"));
+        if !is_y4m {
+            create_dir_all(path).map_err(|e| format!("Couldn't create directory {path}: {e}"))?;
+            return Ok(FrameWriter::Pngs {
+                dir: PathBuf::from(path),
+                width,
+                height,
+                next: 0,
+            });
+        }
+        let out: Box<dyn Write> = if path == "-" {
+            Box::new(stdout().lock())
+        } else {
+            Box::new(File::create(path)?)
+        };
+        let mut out = BufWriter::new(out);
+        // The frame rate is a ratio, whole rates stay exact.
+        let (numerator, denominator) = if fps.fract() == 0.0 {
+            (fps as u32, 1)
+        } else {
+            ((fps * 1000.0).round() as u32, 1000)
+        };
+        // Full resolution chroma keeps any frame size valid, and square
+        // pixels with progressive frames are what the renderer produces.
+        writeln!(
+            out,
+            "YUV4MPEG2 W{width} H{height} F{numerator}:{denominator} Ip A1:1 C444"
+        )?;
+        Ok(FrameWriter::Y4m { out, width, height })
+    }
+
+    /// Write the next RGBA frame.
+    pub fn write_frame(&mut self, frame: &[u8]) -> Result<(), Box<dyn Error>> {
+        match self {
+            FrameWriter::Pngs {
+                dir,
+                width,
+                height,
+                next,
+            } => {
+                let path = dir.join(format!("frame_{next:05}.png"));
+                image::save_buffer(
+                    &path,
+                    frame,
+                    *width as u32,
+                    *height as u32,
+                    image::ColorType::Rgba8,
+                )?;
+                *next += 1;
+            }
+            FrameWriter::Y4m { out, width, height } => {
+                let pixels = *width * *height;
+                let mut planes = vec![0u8; pixels * 3];
+                for (index, rgba) in frame.chunks_exact(4).take(pixels).enumerate() {
+                    let [y, cb, cr] = rgb_to_ycbcr(rgba[0], rgba[1], rgba[2]);
+                    planes[index] = y;
+                    planes[pixels + index] = cb;
+                    planes[2 * pixels + index] = cr;
+                }
+                out.write_all(b"FRAME\n")?;
+                out.write_all(&planes)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush whatever is still buffered.
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        if let FrameWriter::Y4m { mut out, .. } = self {
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// BT.601 in the limited range Y4M readers assume when the header doesn't
+/// say otherwise.
+fn rgb_to_ycbcr(r: u8, g: u8, b: u8) -> [u8; 3] {
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let y = 16.0 + 65.481 * r + 128.553 * g + 24.966 * b;
+    let cb = 128.0 - 37.797 * r - 74.203 * g + 112.0 * b;
+    let cr = 128.0 + 112.0 * r - 93.786 * g - 18.214 * b;
+    [y, cb, cr].map(|value| value.round().clamp(0.0, 255.0) as u8)
+}
+
+/// How the camera moves over the frames of an offscreen render.
+pub enum Motion<'a> {
+    /// Once around the scene from slightly above.
+    Turntable,
+    /// Along a camera path from its start, this many seconds per frame.
+    Path(&'a CameraPath, f32),
+}
+
+/// Draw `frame_count` frames with the camera moving as `motion` says, handing
+/// each to `emit` as soon as it's done.
+pub fn render_frames(
+    world: &mut World,
+    motion: Motion,
+    frame_count: usize,
+    flip_normals: bool,
+    mut emit: impl FnMut(&[u8]) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let distance = match motion {
+        Motion::Path(..) => 0.0,
+        Motion::Turntable => {
+            world.camera.pitch = -TURNTABLE_ELEVATION;
+            world.frame();
+            (world.camera.position - world.camera.pivot).norm()
+        }
+    };
+    let start_yaw = world.camera.yaw;
+    let mut frame = vec![0u8; world.width * world.height * 4];
+    for index in 0..frame_count {
+        match motion {
+            Motion::Path(camera_path, step) => {
+                if let Some(pose) = camera_path.sample(index as f32 * step) {
+                    world.camera.set_pose(&pose);
+                }
+            }
+            Motion::Turntable => {
+                // The last frame stops one step short of the first so the loop
+                // doesn't stutter.
+                world.camera.yaw =
+                    start_yaw + std::f32::consts::TAU * index as f32 / frame_count as f32;
+                world.camera.orbit(distance);
+            }
+        }
+        let view_mat = world.camera.generate_view_mat();
+        world.draw(view_mat, &mut frame, flip_normals);
+        emit(&frame)?;
+    }
+    Ok(())
+}
+
+/// Play `camera_path` at `fps` frames per second without a window, drawing
+/// into a buffer nobody looks at, and log how long the frames took. Frames are
+/// spaced evenly in time, so every run draws exactly the same views.
+pub fn play_headless(world: &mut World, camera_path: &CameraPath, fps: f32, flip_normals: bool) {
+    let frame_count = (camera_path.duration() * fps).floor() as usize + 1;
+    let mut frame = vec![0u8; world.width * world.height * 4];
+    let mut times: Vec<Duration> = Vec::with_capacity(frame_count);
+    for index in 0..frame_count {
+        let Some(pose) = camera_path.sample(index as f32 / fps) else {
+            break;
+        };
+        world.camera.set_pose(&pose);
+        let view_mat = world.camera.generate_view_mat();
+        let start = Instant::now();
+        world.draw(view_mat, &mut frame, flip_normals);
+        times.push(start.elapsed());
+    }
+    let Some((min, max)) = times.iter().min().zip(times.iter().max()) else {
+        info!("Camera path is empty, nothing to draw");
+        return;
+    };
+    let total: Duration = times.iter().sum();
+    info!(
+        "Drew {} frames in {total:?}, {:?} per frame on average, {min:?} fastest and {max:?} slowest",
+        times.len(),
+        total / times.len() as u32
+    );
+}
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::read_to_string;
+
+use winit::event::MouseButton;
+use winit::keyboard::KeyCode;
+use winit_input_helper::WinitInputHelper;
+
+/// Something the viewer can be told to do, independent of what triggers it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    Ascend,
+    Descend,
+    Frame,
+    ToggleCursor,
+    ToggleCamera,
+    /// Click to pick what's under the cursor.
+    Pick,
+    /// Drag to rotate the orbit camera.
+    Rotate,
+    /// Drag to pan the orbit camera.
+    Pan,
+    /// Jump to the camera bookmark in this slot, or save the current view to
+    /// it while `SaveBookmark` is held.
+    Bookmark(u8),
+    SaveBookmark,
+    /// Start or stop recording the camera's path.
+    RecordPath,
+    /// Start or stop playing back the recorded camera path.
+    PlayPath,
+    /// Save what's on screen to a PNG.
+    Screenshot,
+    /// Switch to the next way of drawing the scene.
+    CycleRenderMode,
+    /// Switch to the next debug view, or back to the lit scene after the last.
+    CycleDebugView,
+    Quit,
+}
+
+/// Names actions go by in a bindings file.
+const ACTIONS: &[(&str, Action)] = &[
+    ("move_forward", Action::MoveForward),
+    ("move_back", Action::MoveBack),
+    ("move_left", Action::MoveLeft),
+    ("move_right", Action::MoveRight),
+    ("ascend", Action::Ascend),
+    ("descend", Action::Descend),
+    ("frame", Action::Frame),
+    ("toggle_cursor", Action::ToggleCursor),
+    ("toggle_camera", Action::ToggleCamera),
+    ("pick", Action::Pick),
+    ("rotate", Action::Rotate),
+    ("pan", Action::Pan),
+    ("bookmark_1", Action::Bookmark(1)),
+    ("bookmark_2", Action::Bookmark(2)),
+    ("bookmark_3", Action::Bookmark(3)),
+    ("bookmark_4", Action::Bookmark(4)),
+    ("bookmark_5", Action::Bookmark(5)),
+    ("bookmark_6", Action::Bookmark(6)),
+    ("bookmark_7", Action::Bookmark(7)),
+    ("bookmark_8", Action::Bookmark(8)),
+    ("bookmark_9", Action::Bookmark(9)),
+    ("save_bookmark", Action::SaveBookmark),
+    ("record_path", Action::RecordPath),
+    ("play_path", Action::PlayPath),
+    ("screenshot", Action::Screenshot),
+    ("cycle_render_mode", Action::CycleRenderMode),
+    ("cycle_debug_view", Action::CycleDebugView),
+    ("quit", Action::Quit),
+];
+
+/// Slots the `Bookmark` actions cover.
+pub const BOOKMARK_SLOTS: std::ops::RangeInclusive<u8> = 1..=9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+macro_rules! key_names {
+    ($($key:ident),* $(,)?) => {
+        &[$((stringify!($key), KeyCode::$key)),*]
+    };
+}
+
+/// Keys by their winit name, which is the key at that position on a US
+/// layout whatever the actual layout is.
+const KEYS: &[(&str, KeyCode)] = key_names![
+    KeyA,
+    KeyB,
+    KeyC,
+    KeyD,
+    KeyE,
+    KeyF,
+    KeyG,
+    KeyH,
+    KeyI,
+    KeyJ,
+    KeyK,
+    KeyL,
+    KeyM,
+    KeyN,
+    KeyO,
+    KeyP,
+    KeyQ,
+    KeyR,
+    KeyS,
+    KeyT,
+    KeyU,
+    KeyV,
+    KeyW,
+    KeyX,
+    KeyY,
+    KeyZ,
+    Digit0,
+    Digit1,
+    Digit2,
+    Digit3,
+    Digit4,
+    Digit5,
+    Digit6,
+    Digit7,
+    Digit8,
+    Digit9,
+    Numpad0,
+    Numpad1,
+    Numpad2,
+    Numpad3,
+    Numpad4,
+    Numpad5,
+    Numpad6,
+    Numpad7,
+    Numpad8,
+    Numpad9,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    Space,
+    Tab,
+    Enter,
+    Escape,
+    Backspace,
+    Delete,
+    Insert,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    ArrowUp,
+    ArrowDown,
+    ArrowLeft,
+    ArrowRight,
+    ShiftLeft,
+    ShiftRight,
+    ControlLeft,
+    ControlRight,
+    AltLeft,
+    AltRight,
+    Minus,
+    Equal,
+    BracketLeft,
+    BracketRight,
+    Semicolon,
+    Quote,
+    Comma,
+    Period,
+    Slash,
+    Backslash,
+    Backquote,
+];
+
+const MOUSE_BUTTONS: &[(&str, MouseButton)] = &[
+    ("MouseLeft", MouseButton::Left),
+    ("MouseRight", MouseButton::Right),
+    ("MouseMiddle", MouseButton::Middle),
+    ("MouseBack", MouseButton::Back),
+    ("MouseForward", MouseButton::Forward),
+];
+
+/// What triggers every action, plus how the mouse turns the camera.
+#[derive(Debug, Clone)]
+pub struct Bindings {
+    actions: HashMap<Action, Vec<Input>>,
+    /// Radians turned per pixel of mouse movement.
+    pub mouse_sensitivity: f32,
+    /// Moving the mouse up looks down.
+    pub invert_mouse_y: bool,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use Input::{Key, Mouse};
+        let actions = HashMap::from([
+            (Action::MoveForward, vec![Key(KeyCode::KeyW)]),
+            (Action::MoveBack, vec![Key(KeyCode::KeyS)]),
+            (Action::MoveLeft, vec![Key(KeyCode::KeyA)]),
+            (Action::MoveRight, vec![Key(KeyCode::KeyD)]),
+            (Action::Ascend, vec![Key(KeyCode::Space)]),
+            (Action::Descend, vec![Key(KeyCode::ShiftLeft)]),
+            (Action::Frame, vec![Key(KeyCode::KeyF)]),
+            (Action::ToggleCursor, vec![Key(KeyCode::Tab)]),
+            (Action::ToggleCamera, vec![Key(KeyCode::KeyO)]),
+            (Action::Pick, vec![Mouse(MouseButton::Left)]),
+            (Action::Rotate, vec![Mouse(MouseButton::Left)]),
+            (Action::Pan, vec![Mouse(MouseButton::Middle)]),
+            (Action::Bookmark(1), vec![Key(KeyCode::Digit1)]),
+            (Action::Bookmark(2), vec![Key(KeyCode::Digit2)]),
+            (Action::Bookmark(3), vec![Key(KeyCode::Digit3)]),
+            (Action::Bookmark(4), vec![Key(KeyCode::Digit4)]),
+            (Action::Bookmark(5), vec![Key(KeyCode::Digit5)]),
+            (Action::Bookmark(6), vec![Key(KeyCode::Digit6)]),
+            (Action::Bookmark(7), vec![Key(KeyCode::Digit7)]),
+            (Action::Bookmark(8), vec![Key(KeyCode::Digit8)]),
+            (Action::Bookmark(9), vec![Key(KeyCode::Digit9)]),
+            (
+                Action::SaveBookmark,
+                vec![Key(KeyCode::ControlLeft), Key(KeyCode::ControlRight)],
+            ),
+            (Action::RecordPath, vec![Key(KeyCode::KeyR)]),
+            (Action::PlayPath, vec![Key(KeyCode::KeyP)]),
+            (Action::Screenshot, vec![Key(KeyCode::F12)]),
+            (Action::CycleRenderMode, vec![Key(KeyCode::KeyL)]),
+            (Action::CycleDebugView, vec![Key(KeyCode::KeyV)]),
+            (Action::Quit, vec![Key(KeyCode::Escape)]),
+        ]);
+        Bindings {
+            actions,
+            mouse_sensitivity: 0.003,
+            invert_mouse_y: false,
+        }
+    }
+}
+
+impl Bindings {
+    /// The defaults, overridden by every line of the bindings file at `path`.
+    /// Lines look like `move_forward = KeyZ ArrowUp`, binding every input
+    /// listed and unbinding the defaults, or `mouse_sensitivity = 0.002` and
+    /// `invert_mouse_y = true`. `#` starts a comment.
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn Error>> {
+        let contents = read_to_string(path).map_err(|_| format!("Couldn't open file: {path}"))?;
+        let mut bindings = Bindings::default();
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, value) = line
+                .split_once('=')
+                .ok_or(format!("Missing '=' at line: {}", lineno + 1))?;
+            let (name, value) = (name.trim(), value.trim());
+            match name {
+                "mouse_sensitivity" => {
+                    bindings.mouse_sensitivity = value.parse().map_err(|_| {
+                        format!("Invalid f32 for mouse_sensitivity at line: {}", lineno + 1)
+                    })?;
+                }
+                "invert_mouse_y" => {
+                    bindings.invert_mouse_y = value.parse().map_err(|_| {
+                        format!("Invalid bool for invert_mouse_y at line: {}", lineno + 1)
+                    })?;
+                }
+                _ => {
+                    let action = ACTIONS
+                        .iter()
+                        .find(|(action_name, _)| *action_name == name)
+                        .map(|(_, action)| *action)
+                        .ok_or(format!("Unknown action {name} at line: {}", lineno + 1))?;
+                    let inputs = value
+                        .split_whitespace()
+                        .map(|input| {
+                            parse_input(input)
+                                .ok_or(format!("Unknown input {input} at line: {}", lineno + 1))
+                        })
+                        .collect::<Result<_, _>>()?;
+                    bindings.actions.insert(action, inputs);
+                }
+            }
+        }
+        Ok(bindings)
+    }
+
+    fn inputs(&self, action: Action) -> &[Input] {
+        self.actions.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    /// Whether any input bound to `action` went down this step.
+    pub fn pressed(&self, input: &WinitInputHelper, action: Action) -> bool {
+        self.inputs(action).iter().any(|bound| match bound {
+            Input::Key(key) => input.key_pressed(*key),
+            Input::Mouse(button) => input.mouse_pressed(*button),
+        })
+    }
+
+    /// Whether any input bound to `action` went up this step.
+    pub fn released(&self, input: &WinitInputHelper, action: Action) -> bool {
+        self.inputs(action).iter().any(|bound| match bound {
+            Input::Key(key) => input.key_released(*key),
+            Input::Mouse(button) => input.mouse_released(*button),
+        })
+    }
+
+    /// Whether any input bound to `action` is down.
+    pub fn held(&self, input: &WinitInputHelper, action: Action) -> bool {
+        self.inputs(action).iter().any(|bound| match bound {
+            Input::Key(key) => input.key_held(*key),
+            Input::Mouse(button) => input.mouse_held(*button),
+        })
+    }
+
+    /// Mouse movement this step scaled to radians of yaw and pitch, with the
+    /// y axis flipped if asked for.
+    pub fn mouse_turn(&self, input: &WinitInputHelper) -> (f32, f32) {
+        let (dx, dy) = input.mouse_diff();
+        let dy = if self.invert_mouse_y { -dy } else { dy };
+        (dx * self.mouse_sensitivity, dy * self.mouse_sensitivity)
+    }
+}
+
+fn parse_input(name: &str) -> Option<Input> {
+    KEYS.iter()
+        .find(|(key_name, _)| *key_name == name)
+        .map(|(_, key)| Input::Key(*key))
+        .or_else(|| {
+            MOUSE_BUTTONS
+                .iter()
+                .find(|(button_name, _)| *button_name == name)
+                .map(|(_, button)| Input::Mouse(*button))
+        })
+}
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write;
+use std::fs::{read_to_string, write};
+use std::path::{Path, PathBuf};
+
+use nalgebra::Point3;
+
+const BOOKMARKS_EXTENSION: &str = "bookmarks";
+
+/// A saved camera view.
+#[derive(Debug, Clone, Copy)]
+pub struct Bookmark {
+    pub position: Point3<f32>,
+    pub target: Point3<f32>,
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Vertical field of view in radians.
+    pub fov: f32,
+}
+
+/// Numbered camera views for one model, kept in a text file next to it so they
+/// survive restarts and can be handed to someone along with the model.
+#[derive(Debug)]
+pub struct Bookmarks {
+    path: PathBuf,
+    slots: BTreeMap<u8, Bookmark>,
+}
+
+/// Where the bookmarks for the model at `file_name` are kept.
+pub fn bookmarks_path(file_name: &str) -> PathBuf {
+    PathBuf::from(format!("{file_name}.{BOOKMARKS_EXTENSION}"))
+}
+
+impl Bookmarks {
+    /// No bookmarks yet for the model at `file_name`.
+    pub fn empty(file_name: &str) -> Self {
+        Bookmarks {
+            path: bookmarks_path(file_name),
+            slots: BTreeMap::new(),
+        }
+    }
+
+    /// Bookmarks saved for the model at `file_name`, none if nothing was saved
+    /// for it yet.
+    pub fn load(file_name: &str) -> Result<Self, Box<dyn Error>> {
+        let mut bookmarks = Bookmarks::empty(file_name);
+        if !bookmarks.path.exists() {
+            return Ok(bookmarks);
+        }
+        let contents = read_to_string(&bookmarks.path)?;
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut components = line.split_whitespace();
+            let slot = components
+                .next()
+                .and_then(|slot| slot.parse::<u8>().ok())
+                .ok_or(format!("Invalid bookmark slot at line: {}", lineno + 1))?;
+            let values = components
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| format!("Invalid f32 in bookmark at line: {}", lineno + 1))?;
+            let [px, py, pz, tx, ty, tz, yaw, pitch, fov] = values[..] else {
+                return Err(
+                    format!("Expected 9 values for bookmark at line: {}", lineno + 1).into(),
+                );
+            };
+            bookmarks.slots.insert(
+                slot,
+                Bookmark {
+                    position: Point3::new(px, py, pz),
+                    target: Point3::new(tx, ty, tz),
+                    yaw,
+                    pitch,
+                    fov,
+                },
+            );
+        }
+        Ok(bookmarks)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get(&self, slot: u8) -> Option<&Bookmark> {
+        self.slots.get(&slot)
+    }
+
+    /// Put `bookmark` in `slot` and write every slot back to disk.
+    pub fn save(&mut self, slot: u8, bookmark: Bookmark) -> Result<(), Box<dyn Error>> {
+        self.slots.insert(slot, bookmark);
+        let mut contents = String::from(
+            "# slot, position xyz, target xyz, yaw, pitch and vertical fov in radians\n",
+        );
+        for (slot, bookmark) in &self.slots {
+            let (position, target) = (bookmark.position, bookmark.target);
+            writeln!(
+                contents,
+                "{slot} {} {} {} {} {} {} {} {} {}",
+                position.x,
+                position.y,
+                position.z,
+                target.x,
+                target.y,
+                target.z,
+                bookmark.yaw,
+                bookmark.pitch,
+                bookmark.fov
+            )?;
+        }
+        write(&self.path, contents)?;
+        Ok(())
+    }
+}
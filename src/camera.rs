@@ -0,0 +1,179 @@
+use log::error;
+use nalgebra::{Matrix4, Point3, Vector3};
+use winit::window::{CursorGrabMode, Window};
+use winit_input_helper::WinitInputHelper;
+
+use crate::bindings::{Action, Bindings};
+use crate::camera_path::Pose;
+use crate::mesh::bounds::BoundingSphere;
+
+/// Fly camera speed in scene radii per second, before the scroll wheel scales it.
+pub const FLY_SPEED: f32 = 0.5;
+/// How much one notch of the scroll wheel speeds up or slows down flying.
+pub const FLY_SPEED_STEP: f32 = 1.25;
+/// Rates, per second, at which the fly camera catches up with the speed the
+/// held keys ask for and coasts to a stop once they're released.
+const FLY_ACCELERATION: f32 = 10.0;
+const FLY_DAMPING: f32 = 6.0;
+
+/// How the mouse and keys move the camera.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CameraMode {
+    /// Mouse look with `W`/`A`/`S`/`D` to move.
+    Fly,
+    /// Drag to rotate around a pivot, scroll to dolly and middle drag to pan.
+    Orbit,
+}
+
+pub struct Camera {
+    pub position: Point3<f32>,
+    pub target: Point3<f32>,
+    pub up: Vector3<f32>,
+    pub pitch: f32,
+    pub yaw: f32,
+    /// Point the orbit camera turns around and looks at.
+    pub pivot: Point3<f32>,
+    /// How fast the fly camera is moving, in units per second.
+    pub velocity: Vector3<f32>,
+}
+
+impl Camera {
+    pub fn generate_view_mat(&self) -> Matrix4<f32> {
+        Matrix4::look_at_rh(&self.position, &self.target, &self.up)
+    }
+
+    /// Unit vector the camera looks along for its current yaw and pitch.
+    pub fn direction(&self) -> Vector3<f32> {
+        Vector3::new(
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+            self.pitch.cos() * self.yaw.cos(),
+        )
+    }
+
+    /// Keep looking the same way but back off until `sphere` fits in a view
+    /// whose narrowest half angle is `half_fov`.
+    pub fn frame(&mut self, sphere: &BoundingSphere, half_fov: f32) {
+        // A point still needs some distance to look at.
+        let radius = sphere.radius.max(f32::EPSILON.sqrt());
+        let distance = radius / half_fov.sin();
+        self.target = sphere.center;
+        self.pivot = sphere.center;
+        self.position = sphere.center - self.direction() * distance;
+        self.velocity = Vector3::zeros();
+    }
+
+    pub fn pose(&self) -> Pose {
+        Pose {
+            position: self.position,
+            yaw: self.yaw,
+            pitch: self.pitch,
+        }
+    }
+
+    /// Move to `pose`, keeping the target as far ahead as it was.
+    pub fn set_pose(&mut self, pose: &Pose) {
+        let distance = (self.target - self.position).norm();
+        self.position = pose.position;
+        self.yaw = pose.yaw;
+        self.pitch = pose.pitch;
+        self.target = self.position + self.direction() * distance;
+        self.pivot = self.target;
+        self.velocity = Vector3::zeros();
+    }
+
+    /// Turn to face `pivot` without moving, ready to orbit around it.
+    pub fn set_pivot(&mut self, pivot: Point3<f32>) {
+        self.pivot = pivot;
+        if let Some(direction) = (pivot - self.position).try_normalize(f32::EPSILON) {
+            self.yaw = direction.x.atan2(direction.z);
+            self.pitch = direction.y.clamp(-1.0, 1.0).asin();
+        }
+    }
+
+    /// Place the camera `distance` away from the pivot, looking at it along
+    /// the current yaw and pitch.
+    pub fn orbit(&mut self, distance: f32) {
+        self.target = self.pivot;
+        self.position = self.pivot - self.direction() * distance;
+    }
+}
+
+/// Rotate, dolly and pan the camera around its pivot. `pixel_size` is how far
+/// a window pixel spans at a distance of one in front of the camera.
+pub fn handle_orbit(
+    input: &WinitInputHelper,
+    bindings: &Bindings,
+    camera: &mut Camera,
+    pixel_size: f32,
+) {
+    let mut distance = (camera.position - camera.pivot).norm();
+    if bindings.held(input, Action::Rotate) {
+        let (yaw, pitch) = bindings.mouse_turn(input);
+        camera.yaw -= yaw;
+        camera.pitch -= pitch;
+        let max_pitch = std::f32::consts::FRAC_PI_2 - 0.01;
+        camera.pitch = camera.pitch.clamp(-max_pitch, max_pitch);
+    }
+    if bindings.held(input, Action::Pan) {
+        // Drag the pivot along with the cursor across the screen.
+        let (dx, dy) = input.mouse_diff();
+        let right = camera.direction().cross(&camera.up).normalize();
+        let up = right.cross(&camera.direction());
+        camera.pivot += (-right * dx + up * dy) * pixel_size * distance;
+    }
+    let (_, scroll) = input.scroll_diff();
+    distance = (distance * 0.9f32.powf(scroll)).max(f32::EPSILON.sqrt());
+    camera.orbit(distance);
+}
+
+/// Lock and hide the cursor for mouse look, or free it for picking.
+pub fn grab_cursor(window: &Window, grab: bool) {
+    let mode = if grab {
+        CursorGrabMode::Locked
+    } else {
+        CursorGrabMode::None
+    };
+    if let Err(err) = window.set_cursor_grab(mode) {
+        error!("failed to change cursor grab: {err}");
+    }
+    window.set_cursor_visible(!grab);
+}
+
+/// Move the fly camera with every key held, speeding up towards `speed` units
+/// per second and slowing down once the keys are released. `dt` is the time
+/// in seconds since the last update.
+pub fn handle_keys(
+    input: &WinitInputHelper,
+    bindings: &Bindings,
+    camera: &mut Camera,
+    speed: f32,
+    dt: f32,
+) -> Matrix4<f32> {
+    // Level with the ground whatever the pitch, so W never digs into it.
+    let forward = Vector3::new(camera.yaw.sin(), 0.0, camera.yaw.cos());
+    let right = forward.cross(&camera.up);
+    let mut wanted = Vector3::zeros();
+    for (action, direction) in [
+        (Action::MoveForward, forward),
+        (Action::MoveBack, -forward),
+        (Action::MoveRight, right),
+        (Action::MoveLeft, -right),
+        (Action::Ascend, camera.up),
+        (Action::Descend, -camera.up),
+    ] {
+        if bindings.held(input, action) {
+            wanted += direction;
+        }
+    }
+    let (wanted, rate) = match wanted.try_normalize(f32::EPSILON) {
+        Some(direction) => (direction * speed, FLY_ACCELERATION),
+        None => (Vector3::zeros(), FLY_DAMPING),
+    };
+    // Exponential approach so the feel doesn't change with the frame rate.
+    camera.velocity += (wanted - camera.velocity) * (1.0 - (-rate * dt).exp());
+    let delta = camera.velocity * dt;
+    camera.position += delta;
+    camera.target += delta;
+    camera.generate_view_mat()
+}
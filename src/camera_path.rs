@@ -0,0 +1,169 @@
+use std::error::Error;
+use std::fmt::Write;
+use std::fs::{read_to_string, write};
+
+use nalgebra::{Point3, UnitQuaternion, Vector3};
+
+const CAMERA_PATH_EXTENSION: &str = "campath";
+
+/// Where the camera is and which way it looks.
+#[derive(Debug, Clone, Copy)]
+pub struct Pose {
+    pub position: Point3<f32>,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl Pose {
+    fn orientation(&self) -> UnitQuaternion<f32> {
+        // Turns +z into the direction `Camera::direction` gives for this yaw
+        // and pitch.
+        UnitQuaternion::from_axis_angle(&Vector3::y_axis(), self.yaw)
+            * UnitQuaternion::from_axis_angle(&Vector3::x_axis(), -self.pitch)
+    }
+
+    fn from_orientation(position: Point3<f32>, orientation: UnitQuaternion<f32>) -> Self {
+        let direction = orientation * Vector3::z();
+        Pose {
+            position,
+            yaw: direction.x.atan2(direction.z),
+            pitch: direction.y.clamp(-1.0, 1.0).asin(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe {
+    /// Seconds from the start of the path.
+    pub time: f32,
+    pub pose: Pose,
+}
+
+/// Camera poses over time, played back by smoothly interpolating between them.
+#[derive(Debug, Clone, Default)]
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+}
+
+/// Where the camera path for the model at `file_name` goes by default.
+pub fn camera_path_path(file_name: &str) -> String {
+    format!("{file_name}.{CAMERA_PATH_EXTENSION}")
+}
+
+impl CameraPath {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let contents = read_to_string(path).map_err(|_| format!("Couldn't open file: {path}"))?;
+        let mut camera_path = CameraPath::default();
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let values = line
+                .split_whitespace()
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| format!("Invalid f32 in keyframe at line: {}", lineno + 1))?;
+            let [time, x, y, z, yaw, pitch] = values[..] else {
+                return Err(
+                    format!("Expected 6 values for keyframe at line: {}", lineno + 1).into(),
+                );
+            };
+            if camera_path
+                .keyframes
+                .last()
+                .is_some_and(|last| last.time > time)
+            {
+                return Err(format!("Keyframe goes back in time at line: {}", lineno + 1).into());
+            }
+            camera_path.keyframes.push(Keyframe {
+                time,
+                pose: Pose {
+                    position: Point3::new(x, y, z),
+                    yaw,
+                    pitch,
+                },
+            });
+        }
+        Ok(camera_path)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut contents = String::from("# seconds, position xyz, yaw and pitch in radians\n");
+        for keyframe in &self.keyframes {
+            let pose = keyframe.pose;
+            writeln!(
+                contents,
+                "{} {} {} {} {} {}",
+                keyframe.time,
+                pose.position.x,
+                pose.position.y,
+                pose.position.z,
+                pose.yaw,
+                pose.pitch
+            )?;
+        }
+        write(path, contents)?;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    /// Time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    /// Add a keyframe at `time`, which mustn't be before the last one.
+    pub fn push(&mut self, time: f32, pose: Pose) {
+        debug_assert!(time >= self.duration());
+        self.keyframes.push(Keyframe { time, pose });
+    }
+
+    /// The pose at `time`, following a Catmull-Rom spline through the
+    /// keyframe positions and slerping between their orientations. Times
+    /// outside the path hold its first or last pose.
+    pub fn sample(&self, time: f32) -> Option<Pose> {
+        let last = self.keyframes.len().checked_sub(1)?;
+        if last == 0 {
+            return Some(self.keyframes[0].pose);
+        }
+        let next = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.time <= time)
+            .clamp(1, last);
+        // The segment from `k1` to `k2`, with the keyframes around it shaping
+        // the tangents. The ends repeat so the spline stops at them.
+        let k0 = &self.keyframes[next.saturating_sub(2)];
+        let k1 = &self.keyframes[next - 1];
+        let k2 = &self.keyframes[next];
+        let k3 = &self.keyframes[(next + 1).min(last)];
+        let span = k2.time - k1.time;
+        if span <= 0.0 {
+            return Some(k2.pose);
+        }
+        let u = ((time - k1.time) / span).clamp(0.0, 1.0);
+
+        // Tangents scaled to this segment's length in time, so uneven
+        // keyframe spacing doesn't overshoot.
+        let tangent = |before: &Keyframe, after: &Keyframe| {
+            let gap = after.time - before.time;
+            if gap <= 0.0 {
+                return Vector3::zeros();
+            }
+            (after.pose.position - before.pose.position) * (span / gap)
+        };
+        let m1 = tangent(k0, k2);
+        let m2 = tangent(k1, k3);
+        let (u2, u3) = (u * u, u * u * u);
+        let position = k1.pose.position.coords * (2.0 * u3 - 3.0 * u2 + 1.0)
+            + m1 * (u3 - 2.0 * u2 + u)
+            + k2.pose.position.coords * (-2.0 * u3 + 3.0 * u2)
+            + m2 * (u3 - u2);
+
+        let orientation = k1.pose.orientation().slerp(&k2.pose.orientation(), u);
+        Some(Pose::from_orientation(Point3::from(position), orientation))
+    }
+}
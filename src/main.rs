@@ -1,38 +1,45 @@
+mod animation;
+mod bindings;
+mod bookmarks;
+mod camera;
+mod camera_path;
 mod mesh;
-
-use image::DynamicImage;
-use image::GenericImageView;
-use image::Pixel;
-use mesh::Material;
+mod render;
+mod screenshot;
+mod world;
+
+use animation::{FrameWriter, Motion, play_headless, render_frames};
+use bindings::{Action, BOOKMARK_SLOTS, Bindings};
+use bookmarks::Bookmarks;
+use camera::{
+    Camera, CameraMode, FLY_SPEED, FLY_SPEED_STEP, grab_cursor, handle_keys, handle_orbit,
+};
+use camera_path::CameraPath;
 use mesh::Mesh;
 use mesh::loader::GenericMesh;
+use mesh::subdivide::Subdivision;
+use render::{DebugView, RenderMode};
+use world::{HEIGHT, Light, Object, WIDTH, World};
 
 use clap::Parser;
-use log::{error, info};
-use nalgebra::{Matrix4, Perspective3, Point2, Point3, Point4, Vector3};
+use log::{error, info, warn};
+use nalgebra::{Matrix4, Perspective3, Point3, Vector3};
 use pixels::{Error, Pixels, SurfaceTexture};
-use rayon::prelude::*;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::path::Path;
+use std::time::Duration;
 use winit::dpi::LogicalSize;
 use winit::event::{Event, WindowEvent};
 use winit::event_loop::EventLoop;
-use winit::keyboard::KeyCode;
 use winit::window::WindowBuilder;
 use winit_input_helper::WinitInputHelper;
 
-use crate::mesh::Color;
-use crate::mesh::Normal;
-use crate::mesh::TextureCoord;
-
-const WIDTH: usize = 500;
-const HEIGHT: usize = 500;
+/// How far, in window pixels, the mouse can move between pressing and
+/// releasing a button and still count as a click rather than a drag.
+const CLICK_SLOP: f32 = 4.0;
 
-struct Object {
-    mesh: Box<dyn Mesh>,
-    offset_x: f32,
-    offset_y: f32,
-    offset_z: f32,
-}
+/// Seconds between keyframes while recording a camera path. The spline fills
+/// in the motion between them.
+const RECORD_INTERVAL: f32 = 0.1;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -42,448 +49,382 @@ struct Args {
 
     #[arg(short, long, help = "flip all normals")]
     normals: bool,
-}
-
-struct TriParam {
-    screen_verts: [Point2<f32>; 3],
-    normals: [Vector3<f32>; 3],
-    z_values: [f32; 3],
-    texture_coords: Option<[TextureCoord; 3]>,
-    w_values: [f32; 3],
-}
 
-struct Camera {
-    pub position: Point3<f32>,
-    pub target: Point3<f32>,
-    pub up: Vector3<f32>,
-    pub pitch: f32,
-    pub yaw: f32,
+    #[arg(
+        long,
+        help = "always re-parse the model instead of using its mesh cache"
+    )]
+    no_cache: bool,
+
+    #[arg(
+        long,
+        help = "average STL normals per vertex instead of using facet normals"
+    )]
+    smooth_normals: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "write the loaded scene to this .obj (and a matching .mtl) and exit"
+    )]
+    export: Option<String>,
+
+    #[arg(
+        long,
+        help = "copy textures next to the exported .obj instead of referencing them"
+    )]
+    copy_textures: bool,
+
+    #[arg(
+        long,
+        value_name = "EPSILON",
+        help = "weld vertices closer than EPSILON and drop degenerate, duplicate and unused data"
+    )]
+    weld: Option<f32>,
+
+    #[arg(
+        long,
+        help = "wind every triangle consistently with its neighbours and facing outward, then recompute normals"
+    )]
+    orient: bool,
+
+    #[arg(
+        long,
+        value_name = "RATIO",
+        help = "simplify every mesh down to this fraction of its triangles"
+    )]
+    simplify: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "DISTANCE",
+        help = "stop simplifying before the surface moves further than DISTANCE"
+    )]
+    simplify_error: Option<f32>,
+
+    #[arg(
+        long,
+        value_name = "LEVELS",
+        default_value_t = 0,
+        help = "build this many levels of detail per mesh, each with half the triangles of the last"
+    )]
+    lods: usize,
+
+    #[arg(
+        long,
+        value_name = "LEVELS",
+        default_value_t = 0,
+        help = "subdivide every mesh this many times for a smoother surface"
+    )]
+    subdivide: usize,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Subdivision::Loop,
+        help = "refinement rule used by --subdivide"
+    )]
+    subdivision: Subdivision,
+
+    #[arg(
+        long,
+        help = "move the center of the scene's bounding box to the origin"
+    )]
+    recenter: bool,
+
+    #[arg(
+        long,
+        help = "scale the scene to fit in a unit sphere around its bounding box center"
+    )]
+    normalize: bool,
+
+    #[arg(long, help = "start with the camera framing the whole scene")]
+    frame: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = CameraMode::Fly,
+        help = "camera controls to start with, O switches at runtime"
+    )]
+    camera: CameraMode,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = RenderMode::Shaded,
+        help = "how to draw the scene, L cycles through the modes at runtime"
+    )]
+    render: RenderMode,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "VIEW",
+        help = "show a debug view instead of the lit scene, V cycles through them at runtime"
+    )]
+    debug_view: Option<DebugView>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "read key and mouse bindings from this file instead of using the defaults"
+    )]
+    bindings: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "camera path R records to and P plays back [default: <FILENAME>.campath]"
+    )]
+    camera_path: Option<String>,
+
+    #[arg(
+        long,
+        help = "play the camera path without opening a window and log how long every frame took to draw"
+    )]
+    headless: bool,
+
+    #[arg(
+        long,
+        default_value_t = 30.0,
+        help = "frames per second of camera path playback with --headless and of --video"
+    )]
+    fps: f32,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "render a turntable of the scene to this animated .gif or .png and exit"
+    )]
+    animation: Option<String>,
+
+    #[arg(
+        long,
+        help = "follow the camera path in --animation and --video instead of turning around the scene"
+    )]
+    follow_path: bool,
+
+    #[arg(
+        long,
+        value_name = "COUNT",
+        default_value_t = 36,
+        help = "number of frames in --animation, and in --video when turning around the scene"
+    )]
+    frames: usize,
+
+    #[arg(
+        long,
+        value_name = "WIDTHxHEIGHT",
+        value_parser = parse_size,
+        default_value = "500x500",
+        help = "size of the frames in --animation and --video"
+    )]
+    size: (usize, usize),
+
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 50,
+        help = "milliseconds each frame of --animation is shown for"
+    )]
+    delay: u64,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "render frames to a .y4m video, - for Y4M on stdout, or numbered PNGs in a directory and exit"
+    )]
+    video: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FACTOR",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u8).range(1..=8),
+        help = "redraw screenshots this many times bigger and scale them back down to smooth their edges"
+    )]
+    screenshot_supersample: u8,
 }
 
-struct Light {
-    pub position: Point3<f32>,
-    pub target: Point3<f32>,
-    pub ambient: f32,
-}
-
-struct World {
-    pub camera: Camera,
-    pub light: Light,
-    pub models: Vec<Object>,
-    pub proj_mat: Matrix4<f32>,
+fn parse_size(size: &str) -> Result<(usize, usize), String> {
+    let (width, height) = size
+        .split_once('x')
+        .ok_or("expected WIDTHxHEIGHT, like 500x500")?;
+    match (width.parse(), height.parse()) {
+        (Ok(width @ 1..), Ok(height @ 1..)) => Ok((width, height)),
+        _ => Err(format!("invalid size {size}")),
+    }
 }
 
-impl Camera {
-    pub fn generate_view_mat(&self) -> Matrix4<f32> {
-        Matrix4::look_at_rh(&self.position, &self.target, &self.up)
+/// Render `--animation` or `--video`, turning around the scene or following
+/// `camera_path`.
+fn render_offscreen(
+    cli: &Args,
+    world: &mut World,
+    camera_path: Option<&CameraPath>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let filename = &cli.filename;
+    if let Some(ref animation) = cli.animation {
+        // Frames land on both ends of the path.
+        let motion = match camera_path {
+            Some(camera_path) => Motion::Path(
+                camera_path,
+                camera_path.duration() / cli.frames.saturating_sub(1).max(1) as f32,
+            ),
+            None => Motion::Turntable,
+        };
+        let mut frames = Vec::with_capacity(cli.frames);
+        render_frames(world, motion, cli.frames, cli.normals, |frame| {
+            frames.push(frame.to_vec());
+            Ok(())
+        })?;
+        let delay = Duration::from_millis(cli.delay);
+        animation::write_animation(
+            Path::new(animation),
+            world.width,
+            world.height,
+            &frames,
+            delay,
+        )?;
+        info!(
+            "Rendered {} frames of {filename} to {animation}",
+            frames.len()
+        );
     }
+    if let Some(ref video) = cli.video {
+        let (motion, frame_count) = match camera_path {
+            Some(camera_path) => (
+                Motion::Path(camera_path, 1.0 / cli.fps),
+                (camera_path.duration() * cli.fps).floor() as usize + 1,
+            ),
+            None => (Motion::Turntable, cli.frames),
+        };
+        let mut writer = FrameWriter::create(video, world.width, world.height, cli.fps)?;
+        render_frames(world, motion, frame_count, cli.normals, |frame| {
+            writer.write_frame(frame)
+        })?;
+        writer.finish()?;
+        info!("Rendered {frame_count} frames of {filename} to {video}");
+    }
+    Ok(())
 }
 
-impl World {
-    pub fn new(camera: Camera, light: Light, proj_mat: Matrix4<f32>, models: Vec<Object>) -> Self {
-        World {
-            camera,
-            light,
-            models,
-            proj_mat,
+/// Pick a loader based on the extension of `--filename`, defaulting to OBJ.
+/// glTF files can place several meshes in the scene, everything else loads a
+/// single mesh at the origin.
+fn load_models(cli: &Args) -> Result<Vec<Object>, Box<dyn std::error::Error>> {
+    let extension = Path::new(&cli.filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    let mut meshes = match extension.as_deref() {
+        Some("gltf") | Some("glb") => GenericMesh::from_gltf(&cli.filename)?,
+        Some("stl") => vec![(
+            GenericMesh::from_stl(&cli.filename, cli.smooth_normals)?,
+            Matrix4::identity(),
+        )],
+        Some("ply") => vec![(GenericMesh::from_ply(&cli.filename)?, Matrix4::identity())],
+        _ if cli.no_cache => vec![(GenericMesh::from_obj(&cli.filename)?, Matrix4::identity())],
+        _ => vec![(GenericMesh::from_file(&cli.filename)?, Matrix4::identity())],
+    };
+    if let Some(epsilon) = cli.weld {
+        for (mesh, _) in &mut meshes {
+            let report = mesh.cleanup(epsilon);
+            info!("Cleaned up {}: {report}", cli.filename);
         }
     }
-
-    pub fn draw(&mut self, view_mat: Matrix4<f32>, frame: &mut [u8], flip_normals: bool) {
-        frame.fill(255);
-        let light_dir_world = (self.light.target - self.light.position).normalize();
-        let light_dir_view = (view_mat.fixed_view::<3, 3>(0, 0) * light_dir_world).normalize();
-        let model_with_mats: Vec<(&Object, Matrix4<f32>)> = self
-            .models
-            .iter()
-            .map(|model| -> (&Object, Matrix4<f32>) {
-                (
-                    model,
-                    Matrix4::new(
-                        1.0,
-                        0.0,
-                        0.0,
-                        model.offset_x,
-                        0.0,
-                        1.0,
-                        0.0,
-                        model.offset_y,
-                        0.0,
-                        0.0,
-                        1.0,
-                        model.offset_z,
-                        0.0,
-                        0.0,
-                        0.0,
-                        1.0,
-                    ),
-                )
-            })
-            .collect();
-
-        let mut screen_verts: Vec<Point2<f32>> = vec![];
-        let mut zvalues: Vec<f32> = vec![];
-        let mut wvalues: Vec<f32> = vec![];
-        let mut zbuffer: Vec<AtomicU32> = (0..WIDTH * HEIGHT)
-            .map(|_| AtomicU32::new(f32::to_bits(1.0)))
-            .collect();
-
-        // Iterate over meshes in sorted zbuffer order
-        for (model, model_mat) in &model_with_mats {
-            let mesh = &model.mesh;
-            let normal_mat = (view_mat * model_mat)
-                .fixed_view::<3, 3>(0, 0)
-                .try_inverse()
-                .unwrap()
-                .transpose();
-            let proj = self.proj_mat * view_mat * model_mat;
-
-            for vertex in mesh.verts().iter().copied() {
-                let persproj = proj * Point4::new(vertex.x, vertex.y, vertex.z, 1.0);
-                let ndc_x = persproj.x / persproj.w;
-                let ndc_y = persproj.y / persproj.w;
-                let ndc_z = persproj.z / persproj.w;
-                let ndc_w = 1.0 / persproj.w;
-
-                if !(0.0..=1.0).contains(&ndc_z) {
-                    screen_verts.push(Point2::new(f32::NAN, f32::NAN));
-                } else {
-                    let screen_x = (ndc_x + 1.0) * 0.5 * WIDTH as f32;
-                    let screen_y = (1.0 - ndc_y) * 0.5 * HEIGHT as f32;
-                    screen_verts.push(Point2::new(screen_x, screen_y));
-                }
-                wvalues.push(ndc_w);
-                zvalues.push(ndc_z);
-            }
-
-            let transformed_norms: Vec<Normal> = model
-                .mesh
-                .normals()
-                .iter()
-                .map(|norm| normal_mat * norm)
-                .collect();
-
-            // Draw the triangles
-            for tri in mesh.tris() {
-                let vert1_index = tri.verts[0];
-                let vert2_index = tri.verts[1];
-                let vert3_index = tri.verts[2];
-                let s1 = screen_verts[vert1_index];
-                let s2 = screen_verts[vert2_index];
-                let s3 = screen_verts[vert3_index];
-                if !should_be_rendered(s1, s2, s3) || !is_front_facing(s1, s2, s3) {
-                    continue;
-                }
-                let texture_coords = mesh.texturecoords();
-
-                let n1_idx = tri.norms[0];
-                let n2_idx = tri.norms[1];
-                let n3_idx = tri.norms[2];
-                let mut n1;
-                let mut n2;
-                let mut n3;
-
-                if n1_idx > 0 && n2_idx > 0 && n3_idx > 0 {
-                    n1 = transformed_norms[n1_idx - 1];
-                    n2 = transformed_norms[n2_idx - 1];
-                    n3 = transformed_norms[n3_idx - 1];
-                } else {
-                    n1 = transformed_norms[vert1_index];
-                    n2 = transformed_norms[vert2_index];
-                    n3 = transformed_norms[vert3_index];
-                }
-
-                if flip_normals {
-                    n1 = -n1;
-                    n2 = -n2;
-                    n3 = -n3;
-                }
-
-                let z1 = zvalues[vert1_index];
-                let z2 = zvalues[vert2_index];
-                let z3 = zvalues[vert3_index];
-
-                let w1 = wvalues[vert1_index];
-                let w2 = wvalues[vert2_index];
-                let w3 = wvalues[vert3_index];
-
-                let t1 = texture_coords.get(tri.texes[0]);
-                let t2 = texture_coords.get(tri.texes[1]);
-                let t3 = texture_coords.get(tri.texes[2]);
-
-                self.draw_triangle(
-                    TriParam {
-                        screen_verts: [s1, s2, s3],
-                        normals: [n1, n2, n3],
-                        z_values: [z1, z2, z3],
-                        texture_coords: match (t1, t2, t3) {
-                            (Some(tc1), Some(tc2), Some(tc3)) => Some([*tc1, *tc2, *tc3]),
-                            _ => None,
-                        },
-                        w_values: [w1, w2, w3],
-                    },
-                    &tri.mtl,
-                    frame,
-                    &mut zbuffer,
-                    light_dir_view,
-                );
-            }
-            screen_verts.clear();
-            zvalues.clear();
+    if cli.orient {
+        for (mesh, _) in &mut meshes {
+            let report = mesh.orient();
+            info!("Oriented {}: {report}", cli.filename);
         }
     }
-
-    fn draw_triangle(
-        &self,
-        tri: TriParam,
-        mtl: &Material,
-        frame: &mut [u8],
-        zbuffer: &mut [AtomicU32],
-        light_dir_view: Vector3<f32>,
-    ) {
-        let (x1, y1) = (tri.screen_verts[0].x, tri.screen_verts[0].y);
-        let (x2, y2) = (tri.screen_verts[1].x, tri.screen_verts[1].y);
-        let (x3, y3) = (tri.screen_verts[2].x, tri.screen_verts[2].y);
-        let min_x = (x1.min(x2).min(x3).max(0.0)) as usize;
-        let max_x = (x1.max(x2).max(x3).min(WIDTH as f32 - 1.0) + 1.0) as usize;
-        let min_y = (y1.min(y2).min(y3).max(0.0)) as usize;
-        let max_y = (y1.max(y2).max(y3).min(HEIGHT as f32 - 1.0) + 1.0) as usize;
-
-        if min_x > max_x || min_y > max_y {
-            return;
+    if cli.subdivide > 0 {
+        for (mesh, _) in &mut meshes {
+            mesh.subdivide(cli.subdivision, cli.subdivide);
+            info!("Subdivided {}: {} tris", cli.filename, mesh.tris.len());
         }
-
-        let z1 = tri.z_values[0];
-        let z2 = tri.z_values[1];
-        let z3 = tri.z_values[2];
-        let perspective_warp_1 = tri.w_values[0];
-        let perspective_warp_2 = tri.w_values[1];
-        let perspective_warp_3 = tri.w_values[2];
-        let n1 = tri.normals[0];
-        let n2 = tri.normals[1];
-        let n3 = tri.normals[2];
-        let ambient = self.light.ambient;
-
-        let edge = |(ax, ay): (f32, f32), (bx, by): (f32, f32), (px, py): (f32, f32)| -> f32 {
-            (py - ay) * (bx - ax) - (px - ax) * (by - ay)
-        };
-
-        let row_stride = WIDTH * 4;
-
-        frame
-            .par_chunks_exact_mut(row_stride)
-            .skip(min_y)
-            .take(max_y - min_y)
-            .enumerate()
-            .for_each(|(row_idx, row)| {
-                let y = row_idx + min_y;
-                for x in min_x..=max_x {
-                    let p = (x as f32, y as f32);
-                    let mut w1 = edge((x2, y2), (x3, y3), p);
-                    let mut w2 = edge((x3, y3), (x1, y1), p);
-                    let mut w3 = edge((x1, y1), (x2, y2), p);
-                    if w1 < 0.0 || w2 < 0.0 || w3 < 0.0 {
-                        continue;
-                    }
-
-                    let idx = x * 4;
-                    if idx + 4 > row.len() {
-                        continue;
-                    }
-
-                    let z_index = y * WIDTH + x;
-                    if z_index >= WIDTH * HEIGHT {
-                        continue;
-                    }
-
-                    let area = w1 + w2 + w3;
-
-                    w1 /= area;
-                    w2 /= area;
-                    w3 /= area;
-
-                    let current_z = &zbuffer[z_index];
-                    let current_z_bits = current_z.load(Ordering::Relaxed);
-                    let interpolated_z = w1 * z1 + w2 * z2 + w3 * z3;
-                    if interpolated_z > f32::from_bits(current_z_bits)
-                        || current_z
-                            .compare_exchange(
-                                current_z_bits,
-                                f32::to_bits(interpolated_z),
-                                Ordering::Relaxed,
-                                Ordering::Relaxed,
-                            )
-                            .is_err()
-                    {
-                        continue;
-                    }
-
-                    let mut ka = mtl.ka;
-                    let mut kd = mtl.kd;
-                    let mut ks = mtl.ks;
-
-                    let one_over_z =
-                        w1 * perspective_warp_1 + w2 * perspective_warp_2 + w3 * perspective_warp_3;
-
-                    if let Some([uv1, uv2, uv3]) = tri.texture_coords {
-                        let u_over_z = w1 * uv1.u * perspective_warp_1
-                            + w2 * uv2.u * perspective_warp_2
-                            + w3 * uv3.u * perspective_warp_3;
-                        let v_over_z = w1 * uv1.v * perspective_warp_1
-                            + w2 * uv2.v * perspective_warp_2
-                            + w3 * uv3.v * perspective_warp_3;
-
-                        let interpolated_u = (u_over_z / one_over_z).clamp(0.0, 1.0);
-                        let interpolated_v = 1.0 - (v_over_z / one_over_z).clamp(0.0, 1.0);
-
-                        if let Some(ref tex) = mtl.map_ka {
-                            ka = sample_texture(interpolated_u, interpolated_v, tex);
-                        }
-                        if let Some(ref tex) = mtl.map_kd {
-                            kd = sample_texture(interpolated_u, interpolated_v, tex);
-                        }
-                        if let Some(ref tex) = mtl.map_ks {
-                            ks = sample_texture(interpolated_u, interpolated_v, tex);
-                        }
-                    }
-                    let nx_over_z = w1 * n1.x * perspective_warp_1
-                        + w2 * n2.x * perspective_warp_2
-                        + w3 * n3.x * perspective_warp_3;
-                    let ny_over_z = w1 * n1.y * perspective_warp_1
-                        + w2 * n2.y * perspective_warp_2
-                        + w3 * n3.y * perspective_warp_3;
-                    let nz_over_z = w1 * n1.z * perspective_warp_1
-                        + w2 * n2.z * perspective_warp_2
-                        + w3 * n3.z * perspective_warp_3;
-
-                    let interpolated_normal = Vector3::new(
-                        nx_over_z / one_over_z,
-                        ny_over_z / one_over_z,
-                        nz_over_z / one_over_z,
-                    )
-                    .normalize();
-
-                    let diffuse = light_dir_view.dot(&interpolated_normal).clamp(0.1, 1.0);
-                    let specular = 0.0; //no fancy lighting for now its too laggy
-                    let color = ka * ambient + kd * diffuse + ks * specular;
-                    row[idx..idx + 4].copy_from_slice(&[
-                        (color.r * 255.0) as u8,
-                        (color.g * 255.0) as u8,
-                        (color.b * 255.0) as u8,
-                        (color.a * 255.0) as u8,
-                    ]);
-                }
-            });
     }
-}
-
-/// True if the triangle faces the cam. False, we dont need to draw it.
-#[inline(always)]
-fn is_front_facing(p1: Point2<f32>, p2: Point2<f32>, p3: Point2<f32>) -> bool {
-    (p2.x - p1.x) * (p3.y - p1.y) - (p2.y - p1.y) * (p3.x - p1.x) > 0.0
-}
-
-#[inline(always)]
-fn camera_shift(camera: &mut Camera, delta: Vector3<f32>) {
-    camera.position.x += delta.x;
-    camera.position.z += delta.z;
-    camera.target.x += delta.x;
-    camera.target.z += delta.z;
-}
-
-/// Handle key press turning and etc...
-fn handle_keys(input: &WinitInputHelper, camera: &mut Camera, move_speed: f32) -> Matrix4<f32> {
-    if input.key_held(KeyCode::KeyA) {
-        let mut delta: Vector3<f32> = (camera.position - camera.target)
-            .normalize()
-            .cross(&camera.up)
-            * move_speed;
-        delta -= delta.dot(&camera.up) * camera.up;
-        delta = delta.normalize() * move_speed;
-        camera_shift(camera, delta);
-    } else if input.key_held(KeyCode::KeyD) {
-        let mut delta: Vector3<f32> = (camera.position - camera.target)
-            .normalize()
-            .cross(&camera.up)
-            * move_speed;
-        delta -= delta.dot(&camera.up) * camera.up;
-        delta = delta.normalize() * move_speed;
-        camera_shift(camera, -delta);
-    } else if input.key_held(KeyCode::KeyW) {
-        let mut delta: Vector3<f32> = (camera.position - camera.target).normalize();
-        delta -= delta.dot(&camera.up) * camera.up;
-        delta = delta.normalize() * move_speed;
-        camera_shift(camera, -delta);
-    } else if input.key_held(KeyCode::KeyS) {
-        let mut delta: Vector3<f32> = (camera.position - camera.target).normalize();
-        delta -= delta.dot(&camera.up) * camera.up;
-        delta = delta.normalize() * move_speed;
-        camera_shift(camera, delta);
-    } else if input.key_held(KeyCode::Space) {
-        camera.position.y += move_speed;
-        camera.target.y += move_speed;
-    } else if input.key_held(KeyCode::ShiftLeft) {
-        camera.position.y -= move_speed;
-        camera.target.y -= move_speed;
+    if cli.simplify.is_some() || cli.simplify_error.is_some() {
+        // An error bound on its own simplifies as far as the bound allows.
+        let ratio = cli.simplify.unwrap_or(0.0);
+        let max_error = cli.simplify_error.unwrap_or(f32::INFINITY);
+        for (mesh, _) in &mut meshes {
+            let target = (mesh.tris.len() as f32 * ratio.clamp(0.0, 1.0)) as usize;
+            let removed = mesh.simplify(target, max_error);
+            info!(
+                "Simplified {}: removed {removed} tris, {} left",
+                cli.filename,
+                mesh.tris.len()
+            );
+        }
     }
-    camera.generate_view_mat()
-}
-
-// TODO fix triangles diappearing
-#[inline(always)]
-fn should_be_rendered(s1: Point2<f32>, s2: Point2<f32>, s3: Point2<f32>) -> bool {
-    s1.x.is_finite() && s2.x.is_finite() && s3.x.is_finite()
+    Ok(meshes
+        .into_iter()
+        .map(|(mesh, transform)| {
+            let lods = build_lods(&mesh, cli.lods);
+            Object::new(Box::new(mesh), lods, transform)
+        })
+        .collect())
 }
 
-#[inline(always)]
-fn sample_texture(interp_u: f32, interp_v: f32, tex: &DynamicImage) -> Color {
-    let u = (interp_u * (tex.width() - 1) as f32).round() as u32;
-    let v = (interp_v * (tex.height() - 1) as f32).round() as u32;
-    let pixel = tex
-        .get_pixel(u.min(tex.width() - 1), v.min(tex.height() - 1))
-        .to_rgb();
-    Color {
-        r: pixel[0] as f32 / 255.0,
-        g: pixel[1] as f32 / 255.0,
-        b: pixel[2] as f32 / 255.0,
-        a: 1.0,
+/// Up to `levels` simplified copies of `mesh`, each aiming for half the
+/// triangles of the one before. Stops early once simplifying barely helps, which
+/// happens when most of what's left is seams or open edges.
+fn build_lods(mesh: &GenericMesh, levels: usize) -> Vec<Box<dyn Mesh>> {
+    let mut lods: Vec<Box<dyn Mesh>> = vec![];
+    let mut current = mesh.clone();
+    for level in 1..=levels {
+        let before = current.tris.len();
+        current.simplify(before / 2, f32::INFINITY);
+        if current.tris.len() * 10 > before * 9 {
+            break;
+        }
+        info!("Built LOD {level} with {} tris", current.tris.len());
+        lods.push(Box::new(current.clone()));
     }
+    lods
 }
 
 /// TODO: Add better documentation and clean up code
 fn main() -> Result<(), Error> {
     env_logger::init();
     let cli = Args::parse();
-    let filename = cli.filename;
+    let filename = &cli.filename;
     info!("Loading mesh for {filename}");
-    let mesh = GenericMesh::from_file(&filename).unwrap_or_else(|e| {
+    let models = load_models(&cli).unwrap_or_else(|e| {
         error!("{e:?}");
         std::process::exit(1);
     });
     info!("Done loading mesh for {filename}");
-    let mut input = WinitInputHelper::new();
-    let event_loop = EventLoop::new().unwrap();
-    let window = {
-        let size = LogicalSize::new(WIDTH as f64, HEIGHT as f64);
-        WindowBuilder::new()
-            .with_title("Renderer")
-            .with_inner_size(size)
-            .with_min_inner_size(size)
-            .build(&event_loop)
-            .unwrap()
-    };
-
-    window
-        .set_cursor_grab(winit::window::CursorGrabMode::Locked)
-        .unwrap();
-    window.set_cursor_visible(false);
-
-    let mut pixels = {
-        let window_size = window.inner_size();
-        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(WIDTH as u32, HEIGHT as u32, surface_texture)?
+    let mut bookmarks = Bookmarks::load(filename).unwrap_or_else(|e| {
+        warn!(
+            "Ignoring unreadable bookmarks {:?}: {e}",
+            bookmarks::bookmarks_path(filename)
+        );
+        Bookmarks::empty(filename)
+    });
+    let bindings = match &cli.bindings {
+        Some(path) => Bindings::from_file(path).unwrap_or_else(|e| {
+            error!("{e:?}");
+            std::process::exit(1);
+        }),
+        None => Bindings::default(),
     };
 
+    if let Some(ref export) = cli.export {
+        let objects: Vec<(&dyn Mesh, Matrix4<f32>)> = models
+            .iter()
+            .map(|model| (model.mesh.as_ref(), model.transform))
+            .collect();
+        if let Err(e) = mesh::export::write_obj(Path::new(export), &objects, cli.copy_textures) {
+            error!("{e:?}");
+            std::process::exit(1);
+        }
+        info!("Exported {filename} to {export}");
+        return Ok(());
+    }
     let mut world = World::new(
         Camera {
             position: Point3::new(0.0, 0.0, -10.0),
@@ -491,6 +432,8 @@ fn main() -> Result<(), Error> {
             up: Vector3::new(0.0, 1.0, 0.0),
             pitch: 0.0,
             yaw: 0.0,
+            pivot: Point3::origin(),
+            velocity: Vector3::zeros(),
         },
         Light {
             position: Point3::new(0.0, 1.0, 5.0),
@@ -504,13 +447,80 @@ fn main() -> Result<(), Error> {
             2000.0,
         )
         .to_homogeneous(),
-        vec![Object {
-            mesh: Box::new(mesh),
-            offset_x: 0.0,
-            offset_y: 0.0,
-            offset_z: 0.0,
-        }],
+        models,
     );
+    world.fit(cli.recenter, cli.normalize);
+    world.render_mode = cli.render;
+    world.debug_view = cli.debug_view;
+    if cli.frame {
+        world.frame();
+    }
+    let camera_path_file = cli
+        .camera_path
+        .clone()
+        .unwrap_or_else(|| camera_path::camera_path_path(filename));
+    if cli.animation.is_some() || cli.video.is_some() {
+        let camera_path = cli.follow_path.then(|| {
+            CameraPath::load(&camera_path_file).unwrap_or_else(|e| {
+                error!("{e:?}");
+                std::process::exit(1);
+            })
+        });
+        let (width, height) = cli.size;
+        world.set_size(width, height);
+        if let Err(e) = render_offscreen(&cli, &mut world, camera_path.as_ref()) {
+            error!("{e:?}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if cli.headless {
+        let camera_path = CameraPath::load(&camera_path_file).unwrap_or_else(|e| {
+            error!("{e:?}");
+            std::process::exit(1);
+        });
+        play_headless(&mut world, &camera_path, cli.fps, cli.normals);
+        return Ok(());
+    }
+
+    let mut input = WinitInputHelper::new();
+    let event_loop = EventLoop::new().unwrap();
+    let window = {
+        let size = LogicalSize::new(WIDTH as f64, HEIGHT as f64);
+        WindowBuilder::new()
+            .with_title("Renderer")
+            .with_inner_size(size)
+            .with_min_inner_size(size)
+            .build(&event_loop)
+            .unwrap()
+    };
+
+    let mut pixels = {
+        let window_size = window.inner_size();
+        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
+        Pixels::new(WIDTH as u32, HEIGHT as u32, surface_texture)?
+    };
+
+    let mut camera_mode = cli.camera;
+    if camera_mode == CameraMode::Orbit
+        && let Some(focus) = world.focus()
+    {
+        world.camera.set_pivot(focus.center);
+    }
+
+    let mut cursor_free = false;
+    grab_cursor(&window, camera_mode == CameraMode::Fly);
+    let mut drag = 0.0;
+    // Scale with the scene so tiny and huge models both fly sensibly.
+    let fly_speed = world
+        .bounds()
+        .map_or(1.0, |bounds| bounds.radius.max(f32::EPSILON))
+        * FLY_SPEED;
+    let mut fly_speed_scale = 1.0;
+    let mut camera_path: Option<CameraPath> = None;
+    // Seconds since recording or playback started.
+    let mut recording: Option<f32> = None;
+    let mut playing: Option<f32> = None;
 
     let res = event_loop.run(|event, elwt| {
         let view_mat: Matrix4<f32> = world.camera.generate_view_mat();
@@ -527,23 +537,188 @@ fn main() -> Result<(), Error> {
             }
         }
         if input.update(&event) {
-            if input.key_pressed(KeyCode::Escape) || input.close_requested() {
+            if bindings.pressed(&input, Action::Quit) || input.close_requested() {
+                elwt.exit();
+                return;
+            }
+
+            if let Some(size) = input.window_resized()
+                && let Err(err) = pixels.resize_surface(size.width, size.height)
+            {
+                error!("failed: {err}");
                 elwt.exit();
                 return;
             }
 
-            if let Some(size) = input.window_resized() {
-                if let Err(err) = pixels.resize_surface(size.width, size.height) {
-                    error!("failed: {err}");
-                    elwt.exit();
-                    return;
+            if bindings.pressed(&input, Action::Frame) {
+                world.frame();
+            }
+
+            for slot in BOOKMARK_SLOTS {
+                if !bindings.pressed(&input, Action::Bookmark(slot)) {
+                    continue;
+                }
+                if bindings.held(&input, Action::SaveBookmark) {
+                    match bookmarks.save(slot, world.bookmark()) {
+                        Ok(()) => info!("Saved bookmark {slot} to {:?}", bookmarks.path()),
+                        Err(e) => error!("Couldn't save bookmark {slot}: {e}"),
+                    }
+                } else if let Some(bookmark) = bookmarks.get(slot) {
+                    world.go_to(bookmark);
+                } else {
+                    info!("No bookmark in slot {slot}");
                 }
             }
 
-            let (dx, dy) = input.mouse_diff();
-            let sensitivity = 0.003;
-            world.camera.yaw -= dx * sensitivity;
-            world.camera.pitch -= dy * sensitivity;
+            if bindings.pressed(&input, Action::Screenshot) {
+                let factor = cli.screenshot_supersample as usize;
+                let screenshot = if factor == 1 {
+                    pixels.frame().to_vec()
+                } else {
+                    let (width, height) = (WIDTH * factor, HEIGHT * factor);
+                    let mut frame = vec![0u8; width * height * 4];
+                    world.set_size(width, height);
+                    world.draw(view_mat, &mut frame, cli.normals);
+                    world.set_size(WIDTH, HEIGHT);
+                    screenshot::downsample(&frame, width, height, factor)
+                };
+                match screenshot::save_screenshot(&screenshot, WIDTH, HEIGHT) {
+                    Ok(path) => info!("Saved screenshot to {path:?}"),
+                    Err(e) => error!("Couldn't save screenshot: {e}"),
+                }
+            }
+
+            if bindings.pressed(&input, Action::CycleRenderMode) {
+                world.render_mode = world.render_mode.next();
+                info!("Drawing {:?}", world.render_mode);
+            }
+
+            if bindings.pressed(&input, Action::CycleDebugView) {
+                world.debug_view = DebugView::cycle(world.debug_view);
+                match world.debug_view {
+                    Some(view) => info!("Showing {view:?}"),
+                    None => info!("Showing the lit scene"),
+                }
+            }
+
+            if bindings.pressed(&input, Action::ToggleCursor) {
+                cursor_free = !cursor_free;
+                grab_cursor(&window, camera_mode == CameraMode::Fly && !cursor_free);
+            }
+
+            if bindings.pressed(&input, Action::ToggleCamera) {
+                camera_mode = match camera_mode {
+                    CameraMode::Fly => CameraMode::Orbit,
+                    CameraMode::Orbit => CameraMode::Fly,
+                };
+                world.camera.velocity = Vector3::zeros();
+                if camera_mode == CameraMode::Orbit
+                    && let Some(focus) = world.focus()
+                {
+                    world.camera.set_pivot(focus.center);
+                }
+                grab_cursor(&window, camera_mode == CameraMode::Fly && !cursor_free);
+            }
+
+            // Dragging with the orbit camera shouldn't also pick.
+            if bindings.pressed(&input, Action::Pick) {
+                drag = 0.0;
+            }
+            if let Some((dx, dy)) = bindings
+                .held(&input, Action::Pick)
+                .then(|| input.mouse_diff())
+            {
+                drag += dx.abs() + dy.abs();
+            }
+            let cursor_visible = cursor_free || camera_mode == CameraMode::Orbit;
+            if cursor_visible
+                && bindings.released(&input, Action::Pick)
+                && drag <= CLICK_SLOP
+                && let Some(cursor) = input.cursor()
+            {
+                world.selection = pixels
+                    .window_pos_to_pixel(cursor)
+                    .ok()
+                    .and_then(|(x, y)| world.ray_through(&view_mat, x as f32, y as f32))
+                    .and_then(|ray| world.pick(&ray));
+                match &world.selection {
+                    Some(pick) => info!("Picked {pick}"),
+                    None => info!("Picked nothing"),
+                }
+            }
+
+            // Long stalls, like dragging the window, shouldn't fling the camera.
+            let dt = input
+                .delta_time()
+                .map_or(0.0, |dt| dt.as_secs_f32())
+                .min(0.1);
+
+            if bindings.pressed(&input, Action::RecordPath) {
+                match recording.take() {
+                    Some(time) => {
+                        let path = camera_path.get_or_insert_default();
+                        path.push(time, world.camera.pose());
+                        match path.save(&camera_path_file) {
+                            Ok(()) => info!("Saved {time:.1}s camera path to {camera_path_file}"),
+                            Err(e) => error!("Couldn't save camera path: {e}"),
+                        }
+                    }
+                    None => {
+                        playing = None;
+                        camera_path = Some(CameraPath::default());
+                        recording = Some(0.0);
+                        info!("Recording camera path");
+                    }
+                }
+            }
+
+            if bindings.pressed(&input, Action::PlayPath)
+                && recording.is_none()
+                && playing.take().is_none()
+            {
+                if camera_path.is_none() {
+                    camera_path = CameraPath::load(&camera_path_file)
+                        .inspect_err(|e| error!("Couldn't load camera path: {e}"))
+                        .ok();
+                }
+                playing = camera_path.as_ref().map(|_| 0.0);
+            }
+
+            if let (Some(time), Some(path)) = (&mut playing, &camera_path) {
+                match path.sample(*time) {
+                    Some(pose) if *time <= path.duration() => {
+                        world.camera.set_pose(&pose);
+                        *time += dt;
+                    }
+                    _ => playing = None,
+                }
+                window.request_redraw();
+                return;
+            }
+
+            if let (Some(time), Some(path)) = (&mut recording, &mut camera_path)
+                && (path.is_empty() || *time - path.duration() >= RECORD_INTERVAL)
+            {
+                path.push(*time, world.camera.pose());
+            }
+            if let Some(time) = &mut recording {
+                *time += dt;
+            }
+
+            if camera_mode == CameraMode::Orbit {
+                // proj_mat scales y by the cotangent of the vertical half fov.
+                let window_height = window.inner_size().height.max(1) as f32;
+                let pixel_size = 2.0 / (world.proj_mat[(1, 1)] * window_height);
+                handle_orbit(&input, &bindings, &mut world.camera, pixel_size);
+                window.request_redraw();
+                return;
+            }
+
+            if !cursor_free {
+                let (yaw, pitch) = bindings.mouse_turn(&input);
+                world.camera.yaw -= yaw;
+                world.camera.pitch -= pitch;
+            }
 
             let max_pitch = std::f32::consts::FRAC_PI_2 - 0.01;
             world.camera.pitch = world.camera.pitch.clamp(-max_pitch, max_pitch);
@@ -555,7 +730,16 @@ fn main() -> Result<(), Error> {
             world.camera.target.x = world.camera.position.x + radius * pitch.cos() * yaw.sin();
             world.camera.target.y = world.camera.position.y + radius * pitch.sin();
             world.camera.target.z = world.camera.position.z + radius * pitch.cos() * yaw.cos();
-            handle_keys(&input, &mut world.camera, 1.0);
+            let (_, scroll) = input.scroll_diff();
+            fly_speed_scale =
+                (fly_speed_scale * FLY_SPEED_STEP.powf(scroll)).clamp(1.0 / 64.0, 64.0);
+            handle_keys(
+                &input,
+                &bindings,
+                &mut world.camera,
+                fly_speed * fly_speed_scale,
+                dt,
+            );
             window.request_redraw();
         }
     });
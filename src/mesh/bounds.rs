@@ -0,0 +1,164 @@
+use nalgebra::{Matrix4, Point3, Vector3, Vector4};
+
+use crate::mesh::Vertex;
+
+/// Axis aligned bounding box. An empty box has `min` above `max` and grows to
+/// fit whatever is added to it.
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn empty() -> Self {
+        Aabb {
+            min: Point3::from(Vector3::repeat(f32::INFINITY)),
+            max: Point3::from(Vector3::repeat(f32::NEG_INFINITY)),
+        }
+    }
+
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a Vertex>) -> Self {
+        points.into_iter().fold(Aabb::empty(), |aabb, point| Aabb {
+            min: aabb.min.inf(point),
+            max: aabb.max.sup(point),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..3).any(|axis| self.min[axis] > self.max[axis])
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        nalgebra::center(&self.min, &self.max)
+    }
+
+    pub fn size(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Aabb {
+            min: self.min.inf(&other.min),
+            max: self.max.sup(&other.max),
+        }
+    }
+
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|corner| {
+            // Bit `axis` of the corner index picks min or max along that axis.
+            Point3::from(Vector3::from_fn(|axis, _| {
+                if corner & (1 << axis) == 0 {
+                    self.min[axis]
+                } else {
+                    self.max[axis]
+                }
+            }))
+        })
+    }
+
+    /// The box around this one after `transform`. Rotations make it grow.
+    pub fn transformed(&self, transform: &Matrix4<f32>) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        Aabb::from_points(
+            self.corners()
+                .map(|corner| transform.transform_point(&corner))
+                .iter(),
+        )
+    }
+}
+
+/// Sphere enclosing a set of points. Centered on their bounding box rather than
+/// being the tightest possible sphere, which is plenty for culling and LOD.
+#[derive(Debug, Clone, Copy)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    pub fn from_points(points: &[Vertex]) -> Self {
+        let aabb = Aabb::from_points(points);
+        if aabb.is_empty() {
+            return BoundingSphere {
+                center: Point3::origin(),
+                radius: 0.0,
+            };
+        }
+        let center = aabb.center();
+        let radius = points
+            .iter()
+            .map(|point| (point - center).norm_squared())
+            .fold(0.0, f32::max)
+            .sqrt();
+        BoundingSphere { center, radius }
+    }
+
+    /// Smallest sphere around `center` that holds every sphere in `spheres`.
+    pub fn enclosing<'a>(
+        center: Point3<f32>,
+        spheres: impl IntoIterator<Item = &'a BoundingSphere>,
+    ) -> Self {
+        let radius = spheres
+            .into_iter()
+            .map(|sphere| (sphere.center - center).norm() + sphere.radius)
+            .fold(0.0, f32::max);
+        BoundingSphere { center, radius }
+    }
+
+    /// The sphere after `transform`, grown by its largest axis scale so it still
+    /// encloses everything under non uniform scaling.
+    pub fn transformed(&self, transform: &Matrix4<f32>) -> Self {
+        let scale = (0..3)
+            .map(|axis| transform.fixed_view::<3, 1>(0, axis).norm())
+            .fold(0.0, f32::max);
+        BoundingSphere {
+            center: transform.transform_point(&self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+/// The six planes bounding what a projection matrix can see, each pointing
+/// inwards.
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Planes of the clip volume of `clip`. When `clip` includes a model matrix
+    /// the planes are in that model's space.
+    pub fn from_matrix(clip: &Matrix4<f32>) -> Self {
+        let row = |index: usize| clip.row(index).transpose();
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        Frustum {
+            planes: [w + x, w - x, w + y, w - y, w + z, w - z],
+        }
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.xyz();
+            normal.dot(&sphere.center.coords) + plane.w >= -sphere.radius * normal.norm()
+        })
+    }
+
+    /// Conservative: boxes near the corners of the frustum can pass without
+    /// being inside it.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane normal.
+            let corner = Vector3::from_fn(|axis, _| {
+                if plane[axis] >= 0.0 {
+                    aabb.max[axis]
+                } else {
+                    aabb.min[axis]
+                }
+            });
+            plane.xyz().dot(&corner) + plane.w >= 0.0
+        })
+    }
+}
@@ -0,0 +1,256 @@
+use crate::mesh::Mesh;
+use crate::mesh::bounds::Aabb;
+use crate::mesh::ray::Ray;
+
+/// Most primitives a leaf holds before splitting it is always attempted.
+const LEAF_SIZE: usize = 4;
+/// Leaves are split past this size even when the SAH says it isn't worth it.
+const MAX_LEAF_SIZE: usize = 16;
+/// Buckets the centers are sorted into when looking for the cheapest split.
+const SAH_BINS: usize = 16;
+
+/// Bounding volume hierarchy over anything with a bounding box, e.g. the
+/// triangles of a mesh or the objects in a scene.
+#[derive(Debug, Clone)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    /// Primitive indices, ordered so every node's primitives are contiguous.
+    indices: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BvhNode {
+    pub aabb: Aabb,
+    /// Range of `Bvh::indices` under this node, for inner nodes too.
+    first: usize,
+    count: usize,
+    /// Child node indices, always after this node's own.
+    children: Option<[usize; 2]>,
+}
+
+impl Bvh {
+    /// Build a hierarchy over primitives with the given bounds, splitting where
+    /// the surface area heuristic estimates traversal is cheapest.
+    pub fn build(boxes: &[Aabb]) -> Self {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(boxes.len().div_ceil(LEAF_SIZE) * 2),
+            indices: (0..boxes.len()).collect(),
+        };
+        if !boxes.is_empty() {
+            let centers: Vec<_> = boxes.iter().map(|aabb| aabb.center()).collect();
+            bvh.build_node(boxes, &centers, 0, boxes.len());
+        }
+        bvh
+    }
+
+    /// Hierarchy over the triangles of `mesh`, in model space.
+    pub fn from_mesh(mesh: &dyn Mesh) -> Self {
+        Bvh::build(&triangle_boxes(mesh))
+    }
+
+    fn build_node(
+        &mut self,
+        boxes: &[Aabb],
+        centers: &[nalgebra::Point3<f32>],
+        first: usize,
+        count: usize,
+    ) -> usize {
+        let range = first..first + count;
+        let aabb = self.indices[range.clone()]
+            .iter()
+            .fold(Aabb::empty(), |aabb, index| aabb.union(&boxes[*index]));
+        let node = self.nodes.len();
+        self.nodes.push(BvhNode {
+            aabb,
+            first,
+            count,
+            children: None,
+        });
+        if count <= LEAF_SIZE {
+            return node;
+        }
+
+        let center_bounds =
+            Aabb::from_points(self.indices[range.clone()].iter().map(|i| &centers[*i]));
+        let extent = center_bounds.size();
+        let axis = extent.imax();
+        if extent[axis] <= 0.0 {
+            // Every center in the same spot, no split separates anything.
+            if count <= MAX_LEAF_SIZE {
+                return node;
+            }
+            return self.split_at(node, boxes, centers, first, count / 2);
+        }
+
+        let bin_of = |index: usize| {
+            let offset = (centers[index][axis] - center_bounds.min[axis]) / extent[axis];
+            ((offset * SAH_BINS as f32) as usize).min(SAH_BINS - 1)
+        };
+        let mut bins = [(Aabb::empty(), 0usize); SAH_BINS];
+        for index in &self.indices[range.clone()] {
+            let bin = &mut bins[bin_of(*index)];
+            bin.0 = bin.0.union(&boxes[*index]);
+            bin.1 += 1;
+        }
+
+        // Cost of splitting after every bin, sweeping from both ends.
+        let mut below = [(Aabb::empty(), 0usize); SAH_BINS];
+        let mut sweep = (Aabb::empty(), 0);
+        for bin in 0..SAH_BINS {
+            sweep = (sweep.0.union(&bins[bin].0), sweep.1 + bins[bin].1);
+            below[bin] = sweep;
+        }
+        let mut best: Option<(f32, usize)> = None;
+        let mut above = (Aabb::empty(), 0);
+        for split in (1..SAH_BINS).rev() {
+            above = (above.0.union(&bins[split].0), above.1 + bins[split].1);
+            let (lower, lower_count) = below[split - 1];
+            if lower_count == 0 || above.1 == 0 {
+                continue;
+            }
+            let cost =
+                surface_area(&lower) * lower_count as f32 + surface_area(&above.0) * above.1 as f32;
+            if best.is_none_or(|(best_cost, _)| cost < best_cost) {
+                best = Some((cost, split));
+            }
+        }
+
+        let Some((cost, split)) = best else {
+            return self.split_at(node, boxes, centers, first, count / 2);
+        };
+        if cost >= surface_area(&aabb) * count as f32 && count <= MAX_LEAF_SIZE {
+            return node;
+        }
+        let mut lower_count = 0;
+        for i in range {
+            if bin_of(self.indices[i]) < split {
+                self.indices.swap(i, first + lower_count);
+                lower_count += 1;
+            }
+        }
+        self.split_at(node, boxes, centers, first, lower_count)
+    }
+
+    /// Turn `node` into an inner node whose first child gets the first
+    /// `lower_count` of its primitives.
+    fn split_at(
+        &mut self,
+        node: usize,
+        boxes: &[Aabb],
+        centers: &[nalgebra::Point3<f32>],
+        first: usize,
+        lower_count: usize,
+    ) -> usize {
+        let count = self.nodes[node].count;
+        let left = self.build_node(boxes, centers, first, lower_count);
+        let right = self.build_node(boxes, centers, first + lower_count, count - lower_count);
+        self.nodes[node].children = Some([left, right]);
+        node
+    }
+
+    /// Update every node's bounds for primitives that moved, keeping the
+    /// structure. Much cheaper than a rebuild, but the tree gets worse the
+    /// further things move from where they were when it was built.
+    pub fn refit(&mut self, boxes: &[Aabb]) {
+        // Children always come after their parent.
+        for node in (0..self.nodes.len()).rev() {
+            let aabb = match self.nodes[node].children {
+                Some([left, right]) => self.nodes[left].aabb.union(&self.nodes[right].aabb),
+                None => self
+                    .primitives(&self.nodes[node])
+                    .iter()
+                    .fold(Aabb::empty(), |aabb, index| aabb.union(&boxes[*index])),
+            };
+            self.nodes[node].aabb = aabb;
+        }
+    }
+
+    pub fn root(&self) -> Option<&BvhNode> {
+        self.nodes.first()
+    }
+
+    pub fn primitives(&self, node: &BvhNode) -> &[usize] {
+        &self.indices[node.first..node.first + node.count]
+    }
+
+    /// Call `emit` with the primitives of every node whose box passes
+    /// `visible`, without looking inside nodes holding at most `group` of
+    /// them. Boxes failing `visible` skip their whole subtree.
+    pub fn cull(
+        &self,
+        visible: impl Fn(&Aabb) -> bool,
+        group: usize,
+        mut emit: impl FnMut(&[usize]),
+    ) {
+        let mut stack: Vec<usize> = self.root().map(|_| 0).into_iter().collect();
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !visible(&node.aabb) {
+                continue;
+            }
+            match node.children {
+                Some(children) if node.count > group => stack.extend(children),
+                _ => emit(self.primitives(node)),
+            }
+        }
+    }
+
+    /// Closest primitive along `ray`. `hit` tests one primitive and returns
+    /// the distance along the ray if it's hit closer than the given maximum.
+    pub fn intersect_ray(
+        &self,
+        ray: &Ray,
+        mut hit: impl FnMut(usize, f32) -> Option<f32>,
+    ) -> Option<(usize, f32)> {
+        let mut closest: Option<(usize, f32)> = None;
+        let mut stack: Vec<usize> = self.root().map(|_| 0).into_iter().collect();
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let max = closest.map_or(f32::INFINITY, |(_, distance)| distance);
+            if ray
+                .intersect_aabb(&node.aabb)
+                .is_none_or(|entry| entry > max)
+            {
+                continue;
+            }
+            match node.children {
+                Some([left, right]) => {
+                    // Visit the nearer child first so the further one is more
+                    // likely to be pruned.
+                    let near = |child: usize| ray.intersect_aabb(&self.nodes[child].aabb);
+                    if near(left).unwrap_or(f32::INFINITY) < near(right).unwrap_or(f32::INFINITY) {
+                        stack.extend([right, left]);
+                    } else {
+                        stack.extend([left, right]);
+                    }
+                }
+                None => {
+                    for primitive in self.primitives(node) {
+                        let max = closest.map_or(f32::INFINITY, |(_, distance)| distance);
+                        if let Some(distance) = hit(*primitive, max) {
+                            closest = Some((*primitive, distance));
+                        }
+                    }
+                }
+            }
+        }
+        closest
+    }
+}
+
+/// Model space bounds of every triangle of `mesh`.
+pub fn triangle_boxes(mesh: &dyn Mesh) -> Vec<Aabb> {
+    let verts = mesh.verts();
+    mesh.tris()
+        .iter()
+        .map(|tri| Aabb::from_points(tri.verts.map(|vert| &verts[vert])))
+        .collect()
+}
+
+fn surface_area(aabb: &Aabb) -> f32 {
+    if aabb.is_empty() {
+        return 0.0;
+    }
+    let size = aabb.size();
+    2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+}
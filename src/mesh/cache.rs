@@ -0,0 +1,467 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use nalgebra::{Point3, Vector3};
+
+use crate::mesh::loader::GenericMesh;
+use crate::mesh::{Color, Material, TextureCoord, Triangle};
+
+const MAGIC: &[u8; 4] = b"EMSH";
+/// Bump whenever the layout below changes so stale caches get rebuilt.
+const VERSION: u32 = 4;
+const CACHE_EXTENSION: &str = "meshcache";
+
+/// Where the cache for `file_name` lives: right next to it, e.g. `foo.obj.meshcache`.
+pub fn cache_path(file_name: &str) -> PathBuf {
+    PathBuf::from(format!("{file_name}.{CACHE_EXTENSION}"))
+}
+
+/// Size and modification time of a file the cached mesh was built from.
+#[derive(Debug, PartialEq)]
+struct SourceStamp {
+    size: u64,
+    secs: u64,
+    nanos: u32,
+}
+
+impl SourceStamp {
+    fn of(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let meta = fs::metadata(path)?;
+        let modified = meta.modified()?.duration_since(UNIX_EPOCH)?;
+        Ok(SourceStamp {
+            size: meta.len(),
+            secs: modified.as_secs(),
+            nanos: modified.subsec_nanos(),
+        })
+    }
+}
+
+impl GenericMesh {
+    /// Serialize the mesh to `path`, stamped with the current state of every
+    /// file in `sources` (the OBJ, its MTLs and textures).
+    pub fn write_cache(&self, path: &Path, sources: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+        let mut out: Vec<u8> = vec![];
+        out.extend_from_slice(MAGIC);
+        put_u32(&mut out, VERSION);
+
+        put_u32(&mut out, len_u32(sources.len())?);
+        for source in sources {
+            let stamp = SourceStamp::of(source)?;
+            put_path(&mut out, source)?;
+            put_u64(&mut out, stamp.size);
+            put_u64(&mut out, stamp.secs);
+            put_u32(&mut out, stamp.nanos);
+        }
+
+        // Triangles share their materials, so store each distinct one once.
+        let mut materials: Vec<&Arc<Material>> = vec![];
+        let mut material_ids: HashMap<*const Material, u32> = HashMap::new();
+        let mut tri_materials: Vec<u32> = Vec::with_capacity(self.tris.len());
+        for tri in &self.tris {
+            let index = match material_ids.get(&Arc::as_ptr(&tri.mtl)) {
+                Some(index) => *index,
+                None => {
+                    let index = len_u32(materials.len())?;
+                    material_ids.insert(Arc::as_ptr(&tri.mtl), index);
+                    materials.push(&tri.mtl);
+                    index
+                }
+            };
+            tri_materials.push(index);
+        }
+
+        put_u32(&mut out, len_u32(materials.len())?);
+        for mtl in &materials {
+            put_string(&mut out, &mtl.name)?;
+            put_color(&mut out, mtl.ka);
+            put_color(&mut out, mtl.kd);
+            put_color(&mut out, mtl.ks);
+            put_color(&mut out, mtl.ke);
+            put_f32(&mut out, mtl.transparency);
+            put_color(&mut out, mtl.tf);
+            match mtl.ni {
+                Some(ni) => {
+                    out.push(1);
+                    put_f32(&mut out, ni);
+                }
+                None => out.push(0),
+            }
+            for texture in [
+                &mtl.map_ka_path,
+                &mtl.map_kd_path,
+                &mtl.map_ks_path,
+                &mtl.map_ke_path,
+                &mtl.map_norm_path,
+            ] {
+                match texture {
+                    Some(texture) => {
+                        out.push(1);
+                        put_path(&mut out, texture)?;
+                    }
+                    None => out.push(0),
+                }
+            }
+        }
+
+        put_u64(&mut out, self.verts.len() as u64);
+        for vert in &self.verts {
+            put_f32(&mut out, vert.x);
+            put_f32(&mut out, vert.y);
+            put_f32(&mut out, vert.z);
+        }
+
+        put_u64(&mut out, self.normals.len() as u64);
+        for norm in &self.normals {
+            put_f32(&mut out, norm.x);
+            put_f32(&mut out, norm.y);
+            put_f32(&mut out, norm.z);
+        }
+
+        put_u64(&mut out, self.texture_coords.len() as u64);
+        for tex in &self.texture_coords {
+            put_f32(&mut out, tex.u);
+            put_f32(&mut out, tex.v);
+            put_f32(&mut out, tex.w);
+        }
+
+        put_u64(&mut out, self.colors.len() as u64);
+        for color in &self.colors {
+            put_color(&mut out, *color);
+        }
+
+        put_u64(&mut out, self.tris.len() as u64);
+        for (tri, mtl) in self.tris.iter().zip(tri_materials) {
+            for index in tri.verts.iter().chain(&tri.texes).chain(&tri.norms) {
+                put_u32(&mut out, len_u32(*index)?);
+            }
+            put_u32(&mut out, mtl);
+        }
+
+        // Write to a temporary file first so a crash never leaves a torn cache behind.
+        let tmp_path = path.with_extension(format!("{CACHE_EXTENSION}.tmp"));
+        File::create(&tmp_path)?.write_all(&out)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Load the mesh cached at `path`. Returns `Ok(None)` when there is no
+    /// cache or any of the files it was built from has changed since.
+    pub fn read_cache(path: &Path) -> Result<Option<Self>, Box<dyn Error>> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(_) => return Ok(None),
+        };
+        let mut cache = CacheReader {
+            bytes: &bytes,
+            pos: 0,
+        };
+
+        if cache.take(4)? != MAGIC || cache.u32()? != VERSION {
+            return Ok(None);
+        }
+
+        for _ in 0..cache.u32()? {
+            let source = cache.path()?;
+            let stamp = SourceStamp {
+                size: cache.u64()?,
+                secs: cache.u64()?,
+                nanos: cache.u32()?,
+            };
+            match SourceStamp::of(&source) {
+                Ok(current) if current == stamp => {}
+                _ => return Ok(None),
+            }
+        }
+
+        let mut materials: Vec<Arc<Material>> = vec![];
+        for _ in 0..cache.u32()? {
+            let mut mtl = Material {
+                name: cache.string()?,
+                ka: cache.color()?,
+                kd: cache.color()?,
+                ks: cache.color()?,
+                ke: cache.color()?,
+                transparency: cache.f32()?,
+                tf: cache.color()?,
+                ni: cache.optional_f32()?,
+                ..Default::default()
+            };
+            mtl.map_ka_path = cache.optional_path()?;
+            mtl.map_kd_path = cache.optional_path()?;
+            mtl.map_ks_path = cache.optional_path()?;
+            mtl.map_ke_path = cache.optional_path()?;
+            mtl.map_norm_path = cache.optional_path()?;
+            if let Some(ref texture) = mtl.map_ka_path {
+                mtl.map_ka = Some(image::open(texture)?);
+            }
+            if let Some(ref texture) = mtl.map_kd_path {
+                mtl.map_kd = Some(image::open(texture)?);
+            }
+            if let Some(ref texture) = mtl.map_ks_path {
+                mtl.map_ks = Some(image::open(texture)?);
+            }
+            if let Some(ref texture) = mtl.map_ke_path {
+                mtl.map_ke = Some(image::open(texture)?);
+            }
+            if let Some(ref texture) = mtl.map_norm_path {
+                mtl.map_norm = Some(image::open(texture)?);
+            }
+            materials.push(Arc::new(mtl));
+        }
+
+        let count = cache.u64()? as usize;
+        let mut verts = Vec::with_capacity(cache.capacity_hint(count));
+        for _ in 0..count {
+            verts.push(Point3::new(cache.f32()?, cache.f32()?, cache.f32()?));
+        }
+
+        let count = cache.u64()? as usize;
+        let mut normals = Vec::with_capacity(cache.capacity_hint(count));
+        for _ in 0..count {
+            normals.push(Vector3::new(cache.f32()?, cache.f32()?, cache.f32()?));
+        }
+
+        let count = cache.u64()? as usize;
+        let mut texture_coords = Vec::with_capacity(cache.capacity_hint(count));
+        for _ in 0..count {
+            texture_coords.push(TextureCoord {
+                u: cache.f32()?,
+                v: cache.f32()?,
+                w: cache.f32()?,
+            });
+        }
+
+        let count = cache.u64()? as usize;
+        let mut colors = Vec::with_capacity(cache.capacity_hint(count));
+        for _ in 0..count {
+            colors.push(cache.color()?);
+        }
+
+        let verts_len = verts.len();
+        let count = cache.u64()? as usize;
+        let mut tris = Vec::with_capacity(cache.capacity_hint(count));
+        for _ in 0..count {
+            let verts = [cache.index()?, cache.index()?, cache.index()?];
+            let texes = [cache.index()?, cache.index()?, cache.index()?];
+            let norms = [cache.index()?, cache.index()?, cache.index()?];
+            let mtl = materials
+                .get(cache.index()?)
+                .ok_or("Mesh cache references a missing material")?
+                .clone();
+            // Reject indices the renderer would panic on, so a corrupt cache
+            // is rebuilt instead. Texture indices are 0-based, but faces
+            // without `vt` get 0 even when there are no texture coordinates.
+            // Normal indices are 1-based with 0 meaning "not given", and a
+            // triangle without them uses the normals at its vertex indices.
+            let valid = verts.iter().all(|vert| *vert < verts_len)
+                && texes
+                    .iter()
+                    .all(|tex| *tex == 0 || *tex < texture_coords.len())
+                && norms.iter().all(|norm| *norm <= normals.len())
+                && (norms.iter().all(|norm| *norm > 0)
+                    || verts.iter().all(|vert| *vert < normals.len()));
+            if !valid {
+                return Err("Mesh cache has a triangle index out of range".into());
+            }
+            tris.push(Triangle {
+                verts,
+                texes,
+                norms,
+                mtl,
+            });
+        }
+
+        Ok(Some(GenericMesh {
+            verts,
+            tris,
+            texture_coords,
+            normals,
+            colors,
+        }))
+    }
+}
+
+fn len_u32(len: usize) -> Result<u32, Box<dyn Error>> {
+    u32::try_from(len).map_err(|e| "Mesh too large for the cache format".into())
+}
+
+fn put_u32(out: &mut Vec<u8>, val: u32) {
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, val: u64) {
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+fn put_f32(out: &mut Vec<u8>, val: f32) {
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+fn put_color(out: &mut Vec<u8>, color: Color) {
+    put_f32(out, color.r);
+    put_f32(out, color.g);
+    put_f32(out, color.b);
+    put_f32(out, color.a);
+}
+
+fn put_string(out: &mut Vec<u8>, val: &str) -> Result<(), Box<dyn Error>> {
+    put_u32(out, len_u32(val.len())?);
+    out.extend_from_slice(val.as_bytes());
+    Ok(())
+}
+
+fn put_path(out: &mut Vec<u8>, path: &Path) -> Result<(), Box<dyn Error>> {
+    let path = path
+        .to_str()
+        .ok_or(format!("Can't cache non UTF-8 path {path:?}"))?;
+    put_string(out, path)
+}
+
+/// Cursor over a cache file. Every read is bounds checked so a truncated
+/// cache turns into an error instead of a panic.
+struct CacheReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl CacheReader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], Box<dyn Error>> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or("Mesh cache is truncated")?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Clamp an element count read from the file to what the remaining bytes
+    /// could possibly hold, so a corrupt count can't trigger a huge allocation.
+    fn capacity_hint(&self, count: usize) -> usize {
+        count.min(self.bytes.len() - self.pos)
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, Box<dyn Error>> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, Box<dyn Error>> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn index(&mut self) -> Result<usize, Box<dyn Error>> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn color(&mut self) -> Result<Color, Box<dyn Error>> {
+        Ok(Color {
+            r: self.f32()?,
+            g: self.f32()?,
+            b: self.f32()?,
+            a: self.f32()?,
+        })
+    }
+
+    fn string(&mut self) -> Result<String, Box<dyn Error>> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8(self.take(len)?.to_vec())?)
+    }
+
+    fn path(&mut self) -> Result<PathBuf, Box<dyn Error>> {
+        Ok(PathBuf::from(self.string()?))
+    }
+
+    fn optional_f32(&mut self) -> Result<Option<f32>, Box<dyn Error>> {
+        match self.take(1)?[0] {
+            0 => Ok(None),
+            _ => Ok(Some(self.f32()?)),
+        }
+    }
+
+    fn optional_path(&mut self) -> Result<Option<PathBuf>, Box<dyn Error>> {
+        match self.take(1)?[0] {
+            0 => Ok(None),
+            _ => Ok(Some(self.path()?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const CUBE_CORNER: &str = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\n\
+        vt 0 0\nvt 1 0\nvt 0 1\n\
+        f 1/1 2/2 3/3\nf 1 2 4\n";
+
+    /// Writes `CUBE_CORNER` into a fresh directory and returns the OBJ path.
+    fn write_obj(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("engine-cache-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let obj = dir.join("corner.obj");
+        fs::write(&obj, CUBE_CORNER).unwrap();
+        obj
+    }
+
+    #[test]
+    fn cache_round_trips() {
+        let obj = write_obj("round-trip");
+        let mesh = GenericMesh::from_obj(obj.to_str().unwrap()).unwrap();
+        let cache = obj.with_extension("obj.meshcache");
+        mesh.write_cache(&cache, std::slice::from_ref(&obj))
+            .unwrap();
+        let cached = GenericMesh::read_cache(&cache).unwrap().unwrap();
+        fs::remove_dir_all(obj.parent().unwrap()).unwrap();
+
+        assert_eq!(cached.verts, mesh.verts);
+        assert_eq!(cached.normals, mesh.normals);
+        assert_eq!(cached.texture_coords.len(), mesh.texture_coords.len());
+        assert_eq!(cached.tris.len(), mesh.tris.len());
+        for (cached, tri) in cached.tris.iter().zip(&mesh.tris) {
+            assert_eq!(cached.verts, tri.verts);
+            assert_eq!(cached.texes, tri.texes);
+            assert_eq!(cached.norms, tri.norms);
+        }
+    }
+
+    #[test]
+    fn out_of_range_index_is_rejected() {
+        let obj = write_obj("bad-index");
+        let mut mesh = GenericMesh::from_obj(obj.to_str().unwrap()).unwrap();
+        mesh.tris[0].verts[2] = mesh.verts.len();
+        let cache = obj.with_extension("obj.meshcache");
+        mesh.write_cache(&cache, std::slice::from_ref(&obj))
+            .unwrap();
+        let cached = GenericMesh::read_cache(&cache);
+        fs::remove_dir_all(obj.parent().unwrap()).unwrap();
+
+        assert!(cached.is_err());
+    }
+
+    #[test]
+    fn truncated_cache_is_rejected() {
+        let obj = write_obj("truncated");
+        let mesh = GenericMesh::from_obj(obj.to_str().unwrap()).unwrap();
+        let cache = obj.with_extension("obj.meshcache");
+        mesh.write_cache(&cache, std::slice::from_ref(&obj))
+            .unwrap();
+        let bytes = fs::read(&cache).unwrap();
+        fs::write(&cache, &bytes[..bytes.len() - 5]).unwrap();
+        let cached = GenericMesh::read_cache(&cache);
+        fs::remove_dir_all(obj.parent().unwrap()).unwrap();
+
+        assert!(cached.is_err());
+    }
+}
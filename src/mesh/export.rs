@@ -0,0 +1,424 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use image::DynamicImage;
+use nalgebra::Matrix4;
+
+use crate::mesh::loader::GenericMesh;
+use crate::mesh::{Color, Material, Mesh};
+
+impl GenericMesh {
+    /// Write the mesh to `obj_path`, with its materials in a `.mtl` of the same
+    /// name next to it. See [`write_obj`] for how textures are handled.
+    pub fn write_obj(&self, obj_path: &Path, copy_textures: bool) -> Result<(), Box<dyn Error>> {
+        write_obj(obj_path, &[(self, Matrix4::identity())], copy_textures)
+    }
+}
+
+/// Write `objects` into a single OBJ + MTL pair, each as its own `o` group with
+/// its transform baked into the positions and normals.
+///
+/// Textures that came from files are copied next to the OBJ when
+/// `copy_textures` is set and referenced relative to the OBJ otherwise. Textures
+/// without a file (embedded in a glTF) are always saved next to the OBJ as PNGs.
+pub fn write_obj(
+    obj_path: &Path,
+    objects: &[(&dyn Mesh, Matrix4<f32>)],
+    copy_textures: bool,
+) -> Result<(), Box<dyn Error>> {
+    let out_dir = obj_path.parent().unwrap_or(Path::new("")).to_path_buf();
+    let stem = obj_path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or(format!("Invalid export path {obj_path:?}"))?;
+    let mtl_file = format!("{stem}.mtl");
+
+    let mut materials = MaterialTable::default();
+    let mut obj = String::new();
+    writeln!(obj, "# Exported by engine")?;
+    writeln!(obj, "mtllib {mtl_file}")?;
+
+    // OBJ indices are global across the file, so each object is offset by
+    // everything written before it.
+    let (mut vert_base, mut tex_base, mut norm_base) = (0, 0, 0);
+    for (index, (mesh, transform)) in objects.iter().enumerate() {
+        let normal_mat = transform
+            .fixed_view::<3, 3>(0, 0)
+            .try_inverse()
+            .unwrap_or_else(nalgebra::Matrix3::identity)
+            .transpose();
+        let verts = mesh.verts();
+        let normals = mesh.normals();
+        let texture_coords = mesh.texturecoords();
+        let colors = mesh.colors();
+
+        writeln!(obj, "o object_{index}")?;
+        for (i, vert) in verts.iter().enumerate() {
+            let vert = transform.transform_point(vert);
+            match colors.get(i) {
+                // Vertex colors use the common `v x y z r g b` extension.
+                Some(color) => writeln!(
+                    obj,
+                    "v {} {} {} {} {} {}",
+                    vert.x, vert.y, vert.z, color.r, color.g, color.b
+                )?,
+                None => writeln!(obj, "v {} {} {}", vert.x, vert.y, vert.z)?,
+            }
+        }
+        for tex in texture_coords {
+            writeln!(obj, "vt {} {} {}", tex.u, tex.v, tex.w)?;
+        }
+        for norm in normals {
+            let norm = (normal_mat * norm).try_normalize(0.0).unwrap_or(*norm);
+            writeln!(obj, "vn {} {} {}", norm.x, norm.y, norm.z)?;
+        }
+
+        let mut cur_mtl: Option<*const Material> = None;
+        for tri in mesh.tris() {
+            if cur_mtl != Some(Arc::as_ptr(&tri.mtl)) {
+                cur_mtl = Some(Arc::as_ptr(&tri.mtl));
+                writeln!(obj, "usemtl {}", materials.name_of(&tri.mtl))?;
+            }
+
+            let has_uvs = tri.texes.iter().all(|tex| *tex < texture_coords.len());
+            // Same fallback as the renderer: without normal indices, normals are per vertex.
+            let norms = if tri.norms.iter().all(|norm| *norm > 0) {
+                tri.norms.map(|norm| norm - 1)
+            } else {
+                tri.verts
+            };
+            let has_normals = norms.iter().all(|norm| *norm < normals.len());
+
+            // The loader swaps the first two corners of every face, undo that so a
+            // re-import gives back the same triangles.
+            write!(obj, "f")?;
+            for corner in [1, 0, 2] {
+                write!(obj, " {}", vert_base + tri.verts[corner] + 1)?;
+                match (has_uvs, has_normals) {
+                    (true, true) => write!(
+                        obj,
+                        "/{}/{}",
+                        tex_base + tri.texes[corner] + 1,
+                        norm_base + norms[corner] + 1
+                    )?,
+                    (true, false) => write!(obj, "/{}", tex_base + tri.texes[corner] + 1)?,
+                    (false, true) => write!(obj, "//{}", norm_base + norms[corner] + 1)?,
+                    (false, false) => {}
+                }
+            }
+            writeln!(obj)?;
+        }
+
+        vert_base += verts.len();
+        tex_base += texture_coords.len();
+        norm_base += normals.len();
+    }
+
+    let mtl = materials.write_mtl(&out_dir, stem, copy_textures)?;
+    fs::write(obj_path, obj)?;
+    fs::write(out_dir.join(&mtl_file), mtl)?;
+    Ok(())
+}
+
+/// Materials in the order they were first used, with names made unique and
+/// safe to write into an MTL file.
+#[derive(Default)]
+struct MaterialTable {
+    materials: Vec<(String, Arc<Material>)>,
+    by_ptr: HashMap<*const Material, usize>,
+}
+
+impl MaterialTable {
+    fn name_of(&mut self, mtl: &Arc<Material>) -> String {
+        if let Some(index) = self.by_ptr.get(&Arc::as_ptr(mtl)) {
+            return self.materials[*index].0.clone();
+        }
+        let base: String = match mtl.name.split_whitespace().collect::<Vec<_>>().join("_") {
+            name if name.is_empty() => String::from("default"),
+            name => name,
+        };
+        let mut name = base.clone();
+        let mut suffix = 1;
+        while self.materials.iter().any(|(taken, _)| *taken == name) {
+            name = format!("{base}_{suffix}");
+            suffix += 1;
+        }
+        self.by_ptr.insert(Arc::as_ptr(mtl), self.materials.len());
+        self.materials.push((name.clone(), mtl.clone()));
+        name
+    }
+
+    fn write_mtl(
+        &self,
+        out_dir: &Path,
+        stem: &str,
+        copy_textures: bool,
+    ) -> Result<String, Box<dyn Error>> {
+        let mut mtl_out = String::new();
+        writeln!(mtl_out, "# Exported by engine")?;
+        let mut written_textures: HashMap<PathBuf, String> = HashMap::new();
+        for (name, mtl) in &self.materials {
+            writeln!(mtl_out)?;
+            writeln!(mtl_out, "newmtl {name}")?;
+            write_color(&mut mtl_out, "Ka", mtl.ka)?;
+            write_color(&mut mtl_out, "Kd", mtl.kd)?;
+            write_color(&mut mtl_out, "Ks", mtl.ks)?;
+            write_color(&mut mtl_out, "Ke", mtl.ke)?;
+            if let Some(ni) = mtl.ni {
+                writeln!(mtl_out, "Ni {ni}")?;
+            }
+            writeln!(mtl_out, "d {}", mtl.transparency)?;
+
+            let maps = [
+                ("map_Ka", &mtl.map_ka, &mtl.map_ka_path),
+                ("map_Kd", &mtl.map_kd, &mtl.map_kd_path),
+                ("map_Ks", &mtl.map_ks, &mtl.map_ks_path),
+                ("map_Ke", &mtl.map_ke, &mtl.map_ke_path),
+                ("norm", &mtl.map_norm, &mtl.map_norm_path),
+            ];
+            for (statement, image, path) in maps {
+                let reference = match (image, path) {
+                    (_, Some(path)) => {
+                        texture_reference(path, out_dir, copy_textures, &mut written_textures)?
+                    }
+                    (Some(image), None) => {
+                        let file = format!("{stem}_{name}_{statement}.png");
+                        save_png(image, &out_dir.join(&file))?;
+                        file
+                    }
+                    (None, None) => continue,
+                };
+                writeln!(mtl_out, "{statement} {reference}")?;
+            }
+        }
+        Ok(mtl_out)
+    }
+}
+
+fn write_color(out: &mut String, statement: &str, color: Color) -> std::fmt::Result {
+    writeln!(out, "{statement} {} {} {}", color.r, color.g, color.b)
+}
+
+/// How the MTL should refer to the texture at `path`, copying it into
+/// `out_dir` first if asked to. Textures shared by several materials are only
+/// copied once, and differently located files with the same name get distinct
+/// names.
+fn texture_reference(
+    path: &Path,
+    out_dir: &Path,
+    copy_textures: bool,
+    written: &mut HashMap<PathBuf, String>,
+) -> Result<String, Box<dyn Error>> {
+    if !copy_textures {
+        let out_dir = match out_dir.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => out_dir,
+        };
+        let relative = relative_path(&fs::canonicalize(path)?, &fs::canonicalize(out_dir)?);
+        let relative = relative.to_str().ok_or(format!(
+            "Can't reference non UTF-8 texture path {relative:?}"
+        ))?;
+        // MTL statements are split on whitespace, so such a path wouldn't load back.
+        if relative.contains(char::is_whitespace) {
+            return Err(format!(
+                "Texture path {relative:?} contains whitespace, pass --copy-textures to copy it next to the OBJ instead"
+            )
+            .into());
+        }
+        return Ok(relative.to_owned());
+    }
+    if let Some(file) = written.get(path) {
+        return Ok(file.clone());
+    }
+
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or(format!("Invalid texture path {path:?}"))?;
+    let mut file = file_name.to_owned();
+    let mut suffix = 1;
+    while written.values().any(|taken| *taken == file) {
+        file = format!("{suffix}_{file_name}");
+        suffix += 1;
+    }
+    let dest = out_dir.join(&file);
+    // Exporting next to the source texture would otherwise copy it onto itself.
+    if fs::canonicalize(path).ok() != fs::canonicalize(&dest).ok() {
+        fs::copy(path, &dest)?;
+    }
+    written.insert(path.to_path_buf(), file.clone());
+    Ok(file)
+}
+
+/// `path` relative to `base`, both absolute.
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let path: Vec<Component> = path.components().collect();
+    let base: Vec<Component> = base.components().collect();
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+    let mut relative: PathBuf = base[common..]
+        .iter()
+        .map(|_| Component::ParentDir)
+        .collect();
+    relative.extend(&path[common..]);
+    relative
+}
+
+fn save_png(image: &DynamicImage, path: &Path) -> Result<(), Box<dyn Error>> {
+    image
+        .save_with_format(path, image::ImageFormat::Png)
+        .map_err(|e| format!("Failed to write texture {path:?}: {e}").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::{TextureCoord, Triangle};
+    use image::{Rgb, RgbImage};
+    use nalgebra::{Point3, Vector3};
+
+    fn texture(color: [u8; 3]) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb(color)))
+    }
+
+    #[test]
+    fn mtl_round_trips() {
+        let dir = std::env::temp_dir().join(format!("engine-export-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let plain = Arc::new(Material {
+            name: String::from("plain"),
+            ..Default::default()
+        });
+        let glass = Arc::new(Material {
+            name: String::from("glass"),
+            ke: Color {
+                r: 0.25,
+                g: 0.5,
+                b: 0.75,
+                a: 1.0,
+            },
+            transparency: 0.5,
+            ni: Some(1.5),
+            map_ke: Some(texture([255, 128, 0])),
+            map_norm: Some(texture([128, 128, 255])),
+            ..Default::default()
+        });
+        let tri = |mtl: &Arc<Material>| Triangle {
+            verts: [0, 1, 2],
+            texes: [0, 1, 2],
+            norms: [0; 3],
+            mtl: mtl.clone(),
+        };
+        let mesh = GenericMesh {
+            verts: vec![
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+            ],
+            tris: vec![tri(&plain), tri(&glass)],
+            texture_coords: vec![
+                TextureCoord {
+                    u: 0.0,
+                    v: 0.0,
+                    w: 0.0
+                };
+                3
+            ],
+            normals: vec![Vector3::z(); 3],
+            colors: vec![
+                Color {
+                    r: 1.0,
+                    g: 0.5,
+                    b: 0.0,
+                    a: 1.0,
+                };
+                3
+            ],
+        };
+
+        let obj_path = dir.join("round_trip.obj");
+        mesh.write_obj(&obj_path, false).unwrap();
+        let mtl = fs::read_to_string(dir.join("round_trip.mtl")).unwrap();
+        let loaded = GenericMesh::from_obj(obj_path.to_str().unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.colors.len(), 3);
+        for color in &loaded.colors {
+            assert_eq!([color.r, color.g, color.b, color.a], [1.0, 0.5, 0.0, 1.0]);
+        }
+
+        let plain = &loaded.tris[0].mtl;
+        assert_eq!(plain.transparency, 1.0);
+        assert_eq!(plain.ni, None);
+        assert!(!mtl.contains("Ni 0"));
+
+        let glass = &loaded.tris[1].mtl;
+        assert_eq!(glass.transparency, 0.5);
+        assert_eq!(glass.ni, Some(1.5));
+        assert_eq!([glass.ke.r, glass.ke.g, glass.ke.b], [0.25, 0.5, 0.75]);
+        assert_eq!(
+            glass.map_ke.as_ref().unwrap().to_rgb8().get_pixel(0, 0).0,
+            [255, 128, 0]
+        );
+        assert_eq!(
+            glass.map_norm.as_ref().unwrap().to_rgb8().get_pixel(1, 1).0,
+            [128, 128, 255]
+        );
+    }
+
+    #[test]
+    fn texture_paths_are_relative_to_the_obj() {
+        let dir = std::env::temp_dir().join(format!("engine-export-paths-{}", std::process::id()));
+        let export = |texture_dir: &str| {
+            let texture_path = dir.join(texture_dir).join("diffuse.png");
+            fs::create_dir_all(texture_path.parent().unwrap()).unwrap();
+            texture([0, 255, 0]).save(&texture_path).unwrap();
+            let mtl = Arc::new(Material {
+                map_kd: Some(texture([0, 255, 0])),
+                map_kd_path: Some(texture_path),
+                ..Default::default()
+            });
+            let mesh = GenericMesh {
+                verts: vec![Point3::origin(); 3],
+                tris: vec![Triangle {
+                    verts: [0, 1, 2],
+                    texes: [0; 3],
+                    norms: [0; 3],
+                    mtl,
+                }],
+                texture_coords: vec![],
+                normals: vec![Vector3::z(); 3],
+                colors: vec![],
+            };
+            fs::create_dir_all(dir.join("out")).unwrap();
+            mesh.write_obj(&dir.join("out").join("model.obj"), false)
+        };
+
+        let plain = export("textures").map(|_| {
+            let mtl = fs::read_to_string(dir.join("out").join("model.mtl")).unwrap();
+            let obj = dir.join("out").join("model.obj");
+            (mtl, GenericMesh::from_obj(obj.to_str().unwrap()).unwrap())
+        });
+        let spaced = export("my textures");
+        fs::remove_dir_all(&dir).unwrap();
+
+        let (mtl, loaded) = plain.unwrap();
+        assert!(mtl.contains("map_Kd ../textures/diffuse.png"));
+        assert_eq!(
+            loaded.tris[0]
+                .mtl
+                .map_kd
+                .as_ref()
+                .unwrap()
+                .to_rgb8()
+                .get_pixel(0, 0)
+                .0,
+            [0, 255, 0]
+        );
+        assert!(spaced.is_err());
+    }
+}
@@ -0,0 +1,786 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use image::DynamicImage;
+use log::warn;
+use nalgebra::{Matrix4, Point3, Quaternion, Translation3, UnitQuaternion, Vector3};
+use serde_json::Value;
+
+use crate::mesh::loader::{GenericMesh, vertex_normals};
+use crate::mesh::{Color, Material, TextureCoord, Triangle, WHITE};
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_JSON_CHUNK: u32 = 0x4E4F534A;
+const GLB_BIN_CHUNK: u32 = 0x004E4942;
+const MODE_TRIANGLES: u64 = 4;
+
+/// A mesh placed in the scene by a node, with that node's world transform.
+pub type MeshInstance = (GenericMesh, Matrix4<f32>);
+/// A decoded image and the file it came from, if it wasn't embedded.
+type GltfImage = (Option<PathBuf>, DynamicImage);
+/// The JSON document of a GLB and its optional BIN chunk.
+type GlbChunks = (Value, Option<Vec<u8>>);
+
+/// Everything the accessors, materials and nodes point into.
+struct GltfDocument {
+    json: Value,
+    dir: PathBuf,
+    buffers: Vec<Vec<u8>>,
+    /// Images are decoded lazily and shared by every material using them.
+    images: Vec<Option<GltfImage>>,
+}
+
+impl GenericMesh {
+    /// Load a `.gltf` or `.glb` file. Every node in the default scene that
+    /// references a mesh becomes one entry, paired with its world transform.
+    /// All triangle primitives of a glTF mesh end up in the same `GenericMesh`.
+    pub fn from_gltf(file_name: &str) -> Result<Vec<MeshInstance>, Box<dyn Error>> {
+        let bytes = fs::read(file_name).map_err(|e| format!("Couldn't open file: {file_name}"))?;
+        let (json, bin) = if bytes.starts_with(GLB_MAGIC) {
+            split_glb(&bytes)?
+        } else {
+            (serde_json::from_slice(&bytes)?, None)
+        };
+        let mut dir = PathBuf::from(file_name);
+        dir.pop();
+
+        let mut doc = GltfDocument {
+            buffers: vec![],
+            images: vec![],
+            json,
+            dir,
+        };
+        doc.buffers = load_buffers(&doc, bin)?;
+        doc.images = vec![None; array(&doc.json, "images").len()];
+
+        let mut materials = vec![];
+        for index in 0..array(&doc.json, "materials").len() {
+            materials.push(Arc::new(load_material(&mut doc, index)?));
+        }
+        let default_material: Arc<Material> = Arc::new(Default::default());
+
+        let mut meshes: Vec<GenericMesh> = vec![];
+        for mesh in array(&doc.json, "meshes") {
+            meshes.push(load_mesh(&doc, mesh, &materials, &default_material)?);
+        }
+
+        // Walk the node hierarchy from the scene roots, accumulating transforms.
+        let nodes = array(&doc.json, "nodes");
+        let scene = doc.json["scene"].as_u64().unwrap_or(0) as usize;
+        let roots: Vec<usize> = match array(&doc.json, "scenes").get(scene) {
+            Some(scene) => indices(&scene["nodes"]),
+            None => {
+                let children: Vec<usize> = nodes
+                    .iter()
+                    .flat_map(|node| indices(&node["children"]))
+                    .collect();
+                (0..nodes.len())
+                    .filter(|node| !children.contains(node))
+                    .collect()
+            }
+        };
+
+        let mut instances = vec![];
+        // glTF nodes have at most one parent, so reaching one twice means the
+        // hierarchy loops back on itself and walking it would never end.
+        let mut visited = vec![false; nodes.len()];
+        let mut stack: Vec<(usize, Matrix4<f32>)> = roots
+            .into_iter()
+            .map(|root| (root, Matrix4::identity()))
+            .collect();
+        while let Some((index, parent)) = stack.pop() {
+            let node = nodes
+                .get(index)
+                .ok_or(format!("Missing node {index} in file {file_name}"))?;
+            if std::mem::replace(&mut visited[index], true) {
+                return Err(format!("Node {index} is reached twice in file {file_name}").into());
+            }
+            let world = parent * node_transform(node);
+            if let Some(mesh) = node["mesh"].as_u64() {
+                let mesh = meshes
+                    .get(mesh as usize)
+                    .ok_or(format!("Node {index} references missing mesh {mesh}"))?;
+                instances.push((mesh.clone(), world));
+            }
+            for child in indices(&node["children"]) {
+                stack.push((child, world));
+            }
+        }
+        Ok(instances)
+    }
+}
+
+fn array<'a>(value: &'a Value, key: &str) -> &'a [Value] {
+    value[key]
+        .as_array()
+        .map_or(&[], |values| values.as_slice())
+}
+
+fn indices(value: &Value) -> Vec<usize> {
+    value
+        .as_array()
+        .map(|values| {
+            values
+                .iter()
+                .filter_map(|v| v.as_u64())
+                .map(|v| v as usize)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn floats<const N: usize>(value: &Value, default: [f32; N]) -> [f32; N] {
+    match value.as_array() {
+        Some(values) if values.len() == N => {
+            let mut out = default;
+            for (slot, v) in out.iter_mut().zip(values) {
+                *slot = v.as_f64().unwrap_or(0.0) as f32;
+            }
+            out
+        }
+        _ => default,
+    }
+}
+
+/// Split a binary glTF into its JSON document and the optional BIN chunk.
+fn split_glb(bytes: &[u8]) -> Result<GlbChunks, Box<dyn Error>> {
+    let read_u32 = |offset: usize| -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_le_bytes(
+            bytes
+                .get(offset..offset + 4)
+                .ok_or("GLB file is truncated")?
+                .try_into()?,
+        ))
+    };
+    if read_u32(4)? != 2 {
+        return Err("Only glTF 2.0 GLB files are supported".into());
+    }
+
+    let mut json = None;
+    let mut bin = None;
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let length = read_u32(offset)? as usize;
+        let kind = read_u32(offset + 4)?;
+        let data = bytes
+            .get(offset + 8..offset + 8 + length)
+            .ok_or("GLB chunk runs past the end of the file")?;
+        match kind {
+            GLB_JSON_CHUNK => json = Some(serde_json::from_slice(data)?),
+            GLB_BIN_CHUNK => bin = Some(data.to_vec()),
+            _ => {}
+        }
+        offset += 8 + length;
+    }
+    Ok((json.ok_or("GLB file has no JSON chunk")?, bin))
+}
+
+fn load_buffers(
+    doc: &GltfDocument,
+    mut bin: Option<Vec<u8>>,
+) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    let mut buffers = vec![];
+    for (index, buffer) in array(&doc.json, "buffers").iter().enumerate() {
+        let data = match buffer["uri"].as_str() {
+            Some(uri) => load_uri(&doc.dir, uri)?.0,
+            // Only the first buffer of a GLB may leave out its uri.
+            None if index == 0 => bin
+                .take()
+                .ok_or("Buffer 0 has no uri and there is no GLB BIN chunk")?,
+            None => return Err(format!("Buffer {index} has no uri").into()),
+        };
+        buffers.push(data);
+    }
+    Ok(buffers)
+}
+
+/// Resolve a data URI or a path relative to the glTF file, returning the bytes
+/// and, for files, where they came from.
+fn load_uri(dir: &Path, uri: &str) -> Result<(Vec<u8>, Option<PathBuf>), Box<dyn Error>> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, payload) = data
+            .split_once(";base64,")
+            .ok_or("Only base64 data URIs are supported")?;
+        return Ok((decode_base64(payload)?, None));
+    }
+    let mut path = dir.to_path_buf();
+    path.push(percent_decode(uri));
+    let data = fs::read(&path).map_err(|e| format!("Couldn't open file: {path:?}"))?;
+    Ok((data, Some(path)))
+}
+
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut out = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn decode_base64(payload: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut out = Vec::with_capacity(payload.len() * 3 / 4);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for byte in payload
+        .bytes()
+        .filter(|byte| !byte.is_ascii_whitespace() && *byte != b'=')
+    {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return Err(format!("Invalid base64 character {:?}", byte as char).into()),
+        };
+        acc = (acc << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+/// Read accessor `index` as a flat list of `f32` components, returned with
+/// the number of components per element. Normalized integer accessors are
+/// mapped onto 0..1 (or -1..1) as the spec requires.
+fn read_accessor(doc: &GltfDocument, index: usize) -> Result<(Vec<f32>, usize), Box<dyn Error>> {
+    read_components(
+        doc,
+        index,
+        |component_type, raw, normalized| match component_type {
+            5120 => {
+                let v = raw[0] as i8 as f32;
+                if normalized { (v / 127.0).max(-1.0) } else { v }
+            }
+            5121 => {
+                let v = raw[0] as f32;
+                if normalized { v / 255.0 } else { v }
+            }
+            5122 => {
+                let v = i16::from_le_bytes([raw[0], raw[1]]) as f32;
+                if normalized {
+                    (v / 32767.0).max(-1.0)
+                } else {
+                    v
+                }
+            }
+            5123 => {
+                let v = u16::from_le_bytes([raw[0], raw[1]]) as f32;
+                if normalized { v / 65535.0 } else { v }
+            }
+            5125 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f32,
+            _ => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]),
+        },
+    )
+}
+
+/// Read a scalar index accessor. Indices get their own reader because large
+/// `u32` values don't survive a round trip through `f32`.
+fn read_indices(doc: &GltfDocument, index: usize) -> Result<Vec<usize>, Box<dyn Error>> {
+    let (values, components) =
+        read_components(doc, index, |component_type, raw, _| match component_type {
+            5121 => raw[0] as usize,
+            5123 => u16::from_le_bytes([raw[0], raw[1]]) as usize,
+            5125 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as usize,
+            _ => usize::MAX,
+        })?;
+    if components != 1 {
+        return Err(format!("Index accessor {index} isn't a SCALAR").into());
+    }
+    Ok(values)
+}
+
+/// Decode every component of accessor `index` with `decode`, which gets the
+/// component type, the component's bytes and the normalized flag.
+fn read_components<T: Clone + Default>(
+    doc: &GltfDocument,
+    index: usize,
+    decode: impl Fn(u64, &[u8], bool) -> T,
+) -> Result<(Vec<T>, usize), Box<dyn Error>> {
+    let accessor = array(&doc.json, "accessors")
+        .get(index)
+        .ok_or(format!("Missing accessor {index}"))?;
+    let count = accessor["count"].as_u64().ok_or("Accessor without count")? as usize;
+    let components = match accessor["type"].as_str() {
+        Some("SCALAR") => 1,
+        Some("VEC2") => 2,
+        Some("VEC3") => 3,
+        Some("VEC4") | Some("MAT2") => 4,
+        Some("MAT3") => 9,
+        Some("MAT4") => 16,
+        other => return Err(format!("Unknown accessor type {other:?}").into()),
+    };
+    let component_type = accessor["componentType"].as_u64().unwrap_or(0);
+    let size = match component_type {
+        5120 | 5121 => 1,
+        5122 | 5123 => 2,
+        5125 | 5126 => 4,
+        other => return Err(format!("Unknown accessor component type {other}").into()),
+    };
+    let normalized = accessor["normalized"].as_bool().unwrap_or(false);
+    if accessor.get("sparse").is_some() {
+        warn!("Sparse accessor {index} isn't supported, reading its base values only");
+    }
+
+    // Accessors without a buffer view are all zeros.
+    let Some(view) = accessor["bufferView"].as_u64() else {
+        return Ok((vec![T::default(); count * components], components));
+    };
+    let view = array(&doc.json, "bufferViews")
+        .get(view as usize)
+        .ok_or(format!("Missing buffer view {view}"))?;
+    let buffer = doc
+        .buffers
+        .get(view["buffer"].as_u64().unwrap_or(0) as usize)
+        .ok_or("Buffer view references a missing buffer")?;
+    let view_start = view["byteOffset"].as_u64().unwrap_or(0) as usize;
+    let view_length = view["byteLength"].as_u64().unwrap_or(0) as usize;
+    let data = view_start
+        .checked_add(view_length)
+        .and_then(|view_end| buffer.get(view_start..view_end))
+        .ok_or(format!(
+            "Buffer view of accessor {index} runs past the end of its buffer"
+        ))?;
+    let start = accessor["byteOffset"].as_u64().unwrap_or(0) as usize;
+    let element = components * size;
+    let stride = view["byteStride"]
+        .as_u64()
+        .map_or(element, |stride| stride as usize);
+
+    // Check the whole accessor fits before allocating anything for its count.
+    let end = match count {
+        0 => Some(start),
+        _ => (count - 1)
+            .checked_mul(stride)
+            .and_then(|last| last.checked_add(start))
+            .and_then(|last| last.checked_add(element)),
+    };
+    if end.is_none_or(|end| end > data.len()) {
+        return Err(format!("Accessor {index} reads past the end of its buffer view").into());
+    }
+
+    let mut values = Vec::with_capacity(count * components);
+    for row in 0..count {
+        for component in 0..components {
+            let offset = start + row * stride + component * size;
+            values.push(decode(
+                component_type,
+                &data[offset..offset + size],
+                normalized,
+            ));
+        }
+    }
+    Ok((values, components))
+}
+
+fn load_image(
+    doc: &mut GltfDocument,
+    texture: &Value,
+) -> Result<Option<GltfImage>, Box<dyn Error>> {
+    let Some(texture) = texture["index"].as_u64() else {
+        return Ok(None);
+    };
+    let source = array(&doc.json, "textures")
+        .get(texture as usize)
+        .and_then(|texture| texture["source"].as_u64())
+        .ok_or(format!("Texture {texture} has no image source"))? as usize;
+    if let Some(Some(image)) = doc.images.get(source) {
+        return Ok(Some(image.clone()));
+    }
+
+    let image = array(&doc.json, "images")
+        .get(source)
+        .ok_or(format!("Missing image {source}"))?;
+    let (data, path) = match (image["uri"].as_str(), image["bufferView"].as_u64()) {
+        (Some(uri), _) => load_uri(&doc.dir, uri)?,
+        (None, Some(view)) => {
+            let view = array(&doc.json, "bufferViews")
+                .get(view as usize)
+                .ok_or(format!("Missing buffer view {view}"))?;
+            let buffer = doc
+                .buffers
+                .get(view["buffer"].as_u64().unwrap_or(0) as usize)
+                .ok_or("Buffer view references a missing buffer")?;
+            let start = view["byteOffset"].as_u64().unwrap_or(0) as usize;
+            let length = view["byteLength"].as_u64().unwrap_or(0) as usize;
+            let data = buffer
+                .get(start..start + length)
+                .ok_or(format!("Image {source} runs past the end of its buffer"))?;
+            (data.to_vec(), None)
+        }
+        _ => return Err(format!("Image {source} has neither a uri nor a buffer view").into()),
+    };
+    let loaded = (
+        path,
+        image::load_from_memory(&data)
+            .map_err(|e| format!("Failed to decode image {source}: {e}"))?,
+    );
+    doc.images[source] = Some(loaded.clone());
+    Ok(Some(loaded))
+}
+
+/// Map a metallic-roughness material onto our Ka/Kd/Ks model. The base color
+/// drives ambient and diffuse, and the specular color blends from the 4%
+/// dielectric reflectance towards the base color as the surface gets more
+/// metallic, dimmed by roughness.
+fn load_material(doc: &mut GltfDocument, index: usize) -> Result<Material, Box<dyn Error>> {
+    let material = array(&doc.json, "materials")[index].clone();
+    let pbr = &material["pbrMetallicRoughness"];
+    let [r, g, b, a] = floats(&pbr["baseColorFactor"], [1.0, 1.0, 1.0, 1.0]);
+    let metallic = pbr["metallicFactor"].as_f64().unwrap_or(1.0) as f32;
+    let roughness = pbr["roughnessFactor"].as_f64().unwrap_or(1.0) as f32;
+    let [er, eg, eb] = floats(&material["emissiveFactor"], [0.0, 0.0, 0.0]);
+
+    let base = Color { r, g, b, a: 1.0 };
+    let dielectric = Color {
+        r: 0.04,
+        g: 0.04,
+        b: 0.04,
+        a: 1.0,
+    };
+    let ks = (dielectric * (1.0 - metallic) + base * metallic) * (1.0 - roughness);
+
+    let mut mtl = Material {
+        name: material["name"]
+            .as_str()
+            .map_or_else(|| format!("material_{index}"), String::from),
+        ka: base,
+        kd: base,
+        ks,
+        ke: Color {
+            r: er,
+            g: eg,
+            b: eb,
+            a: 1.0,
+        },
+        transparency: a,
+        ..Default::default()
+    };
+
+    // glTF multiplies textures by their factors, while our sampled texels
+    // replace the material color, so the factors are baked into the images.
+    if let Some((path, image)) = load_image(doc, &pbr["baseColorTexture"])? {
+        let (path, image) = tint(image, path, [r, g, b]);
+        mtl.map_ka = Some(image.clone());
+        mtl.map_kd = Some(image);
+        mtl.map_ka_path = path.clone();
+        mtl.map_kd_path = path;
+    }
+    // A zero emissive factor turns the emissive texture off entirely.
+    if er + eg + eb > 0.0
+        && let Some((path, image)) = load_image(doc, &material["emissiveTexture"])?
+    {
+        let (path, image) = tint(image, path, [er, eg, eb]);
+        mtl.map_ke = Some(image);
+        mtl.map_ke_path = path;
+    }
+    if let Some((path, image)) = load_image(doc, &material["normalTexture"])? {
+        mtl.map_norm = Some(image);
+        mtl.map_norm_path = path;
+    }
+    Ok(mtl)
+}
+
+/// Multiply the color channels of `image` by `factor`. A tinted image no
+/// longer matches its file, so its path is dropped and an export writes the
+/// tinted pixels out instead of referencing the original.
+fn tint(image: DynamicImage, path: Option<PathBuf>, factor: [f32; 3]) -> GltfImage {
+    if factor == [1.0; 3] {
+        return (path, image);
+    }
+    let mut pixels = image.into_rgba8();
+    for pixel in pixels.pixels_mut() {
+        for (channel, factor) in pixel.0.iter_mut().zip(factor) {
+            *channel = (*channel as f32 * factor).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    (None, DynamicImage::ImageRgba8(pixels))
+}
+
+fn load_mesh(
+    doc: &GltfDocument,
+    mesh: &Value,
+    materials: &[Arc<Material>],
+    default_material: &Arc<Material>,
+) -> Result<GenericMesh, Box<dyn Error>> {
+    let mut verts = vec![];
+    let mut normals = vec![];
+    let mut texture_coords = vec![];
+    let mut colors = vec![];
+    let mut tris: Vec<Triangle> = vec![];
+    let mut any_colors = false;
+
+    for primitive in array(mesh, "primitives") {
+        if primitive["mode"].as_u64().unwrap_or(MODE_TRIANGLES) != MODE_TRIANGLES {
+            warn!("Skipping non-triangle primitive in mesh {:?}", mesh["name"]);
+            continue;
+        }
+        let attributes = &primitive["attributes"];
+        let Some(position) = attributes["POSITION"].as_u64() else {
+            continue;
+        };
+        let first = verts.len();
+        let (positions, components) = read_accessor(doc, position as usize)?;
+        if components < 3 {
+            return Err(format!("POSITION accessor {position} isn't a VEC3").into());
+        }
+        for row in positions.chunks_exact(components) {
+            verts.push(Point3::new(row[0], row[1], row[2]));
+        }
+        let vertex_count = verts.len() - first;
+
+        let triangle_indices: Vec<usize> = match primitive["indices"].as_u64() {
+            Some(accessor) => read_indices(doc, accessor as usize)?,
+            None => (0..vertex_count).collect(),
+        };
+        if let Some(bad) = triangle_indices
+            .iter()
+            .find(|index| **index >= vertex_count)
+        {
+            return Err(format!(
+                "Primitive index {bad} is out of range for {vertex_count} vertices"
+            )
+            .into());
+        }
+        let material = match primitive["material"].as_u64() {
+            Some(material) => materials
+                .get(material as usize)
+                .ok_or(format!("Missing material {material}"))?
+                .clone(),
+            None => default_material.clone(),
+        };
+        let first_tri = tris.len();
+        for tri in triangle_indices.chunks_exact(3) {
+            // UVs and normals are per vertex in glTF, so they share vertex indices.
+            // The first two corners are swapped to match how `clip_ears` winds OBJ faces.
+            let tri = [first + tri[1], first + tri[0], first + tri[2]];
+            tris.push(Triangle {
+                verts: tri,
+                texes: tri,
+                norms: [0; 3],
+                mtl: material.clone(),
+            });
+        }
+
+        match attributes["NORMAL"].as_u64() {
+            Some(accessor) => {
+                let (values, components) = read_accessor(doc, accessor as usize)?;
+                if components < 3 {
+                    return Err(format!("NORMAL accessor {accessor} isn't a VEC3").into());
+                }
+                check_vertex_count("NORMAL", accessor, values.len() / components, vertex_count)?;
+                for row in values.chunks_exact(components) {
+                    normals.push(
+                        Vector3::new(row[0], row[1], row[2])
+                            .try_normalize(f32::EPSILON)
+                            .unwrap_or_else(Vector3::zeros),
+                    );
+                }
+            }
+            None => {
+                // Recompute for just this primitive, with its indices made local again.
+                let local: Vec<Triangle> = tris[first_tri..]
+                    .iter()
+                    .map(|tri| Triangle {
+                        verts: tri.verts.map(|vert| vert - first),
+                        ..tri.clone()
+                    })
+                    .collect();
+                normals.extend(vertex_normals(&verts[first..], &local));
+            }
+        }
+
+        match attributes["TEXCOORD_0"].as_u64() {
+            Some(accessor) => {
+                let (values, components) = read_accessor(doc, accessor as usize)?;
+                if components < 2 {
+                    return Err(format!("TEXCOORD_0 accessor {accessor} isn't a VEC2").into());
+                }
+                check_vertex_count(
+                    "TEXCOORD_0",
+                    accessor,
+                    values.len() / components,
+                    vertex_count,
+                )?;
+                for row in values.chunks_exact(components) {
+                    // glTF puts the UV origin at the top left, OBJ (and our sampler) at the bottom left.
+                    texture_coords.push(TextureCoord {
+                        u: row[0],
+                        v: 1.0 - row[1],
+                        w: 0.0,
+                    });
+                }
+            }
+            None => texture_coords.resize(
+                verts.len(),
+                TextureCoord {
+                    u: 0.0,
+                    v: 0.0,
+                    w: 0.0,
+                },
+            ),
+        }
+
+        match attributes["COLOR_0"].as_u64() {
+            Some(accessor) => {
+                any_colors = true;
+                let (values, components) = read_accessor(doc, accessor as usize)?;
+                if components < 3 {
+                    return Err(format!("COLOR_0 accessor {accessor} isn't a VEC3 or VEC4").into());
+                }
+                check_vertex_count("COLOR_0", accessor, values.len() / components, vertex_count)?;
+                for row in values.chunks_exact(components) {
+                    colors.push(Color {
+                        r: row[0],
+                        g: row[1],
+                        b: row[2],
+                        a: row.get(3).copied().unwrap_or(1.0),
+                    });
+                }
+            }
+            None => colors.resize(verts.len(), WHITE),
+        }
+    }
+
+    // Colors are all or nothing per mesh, drop the white padding if no primitive had any.
+    if !any_colors {
+        colors.clear();
+    }
+
+    Ok(GenericMesh {
+        verts,
+        tris,
+        texture_coords,
+        normals,
+        colors,
+    })
+}
+
+/// Per-vertex attributes are indexed like POSITION, so a short or long one would
+/// misalign everything after it in the mesh.
+fn check_vertex_count(
+    name: &str,
+    accessor: u64,
+    count: usize,
+    vertex_count: usize,
+) -> Result<(), Box<dyn Error>> {
+    if count != vertex_count {
+        return Err(format!(
+            "{name} accessor {accessor} has {count} elements but POSITION has {vertex_count}"
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Local transform of a node, from either its matrix or its TRS properties.
+fn node_transform(node: &Value) -> Matrix4<f32> {
+    if node.get("matrix").is_some() {
+        // glTF matrices are column major, which is what from_column_slice expects.
+        let identity: [f32; 16] = Matrix4::identity().as_slice().try_into().unwrap();
+        let matrix = floats(&node["matrix"], identity);
+        return Matrix4::from_column_slice(&matrix);
+    }
+    let [tx, ty, tz] = floats(&node["translation"], [0.0, 0.0, 0.0]);
+    let [qx, qy, qz, qw] = floats(&node["rotation"], [0.0, 0.0, 0.0, 1.0]);
+    let [sx, sy, sz] = floats(&node["scale"], [1.0, 1.0, 1.0]);
+    Translation3::new(tx, ty, tz).to_homogeneous()
+        * UnitQuaternion::from_quaternion(Quaternion::new(qw, qx, qy, qz)).to_homogeneous()
+        * Matrix4::new_nonuniform_scaling(&Vector3::new(sx, sy, sz))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A single triangle whose positions live in the file `bin`, next to the document.
+    fn triangle(bin: &str) -> Value {
+        json!({
+            "buffers": [{ "uri": bin, "byteLength": 36 }],
+            "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" },
+            ],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 } }] }],
+            "nodes": [{ "mesh": 0 }],
+            "scenes": [{ "nodes": [0] }],
+        })
+    }
+
+    fn load(
+        name: &str,
+        edit: impl FnOnce(&mut Value),
+        bin: &[u8],
+    ) -> Result<Vec<MeshInstance>, Box<dyn Error>> {
+        let stem = format!("engine-gltf-{name}-{}", std::process::id());
+        let dir = std::env::temp_dir();
+        let mut doc = triangle(&format!("{stem}.bin"));
+        edit(&mut doc);
+        fs::write(dir.join(format!("{stem}.bin")), bin).unwrap();
+        fs::write(dir.join(format!("{stem}.gltf")), doc.to_string()).unwrap();
+        let instances = GenericMesh::from_gltf(dir.join(format!("{stem}.gltf")).to_str().unwrap());
+        fs::remove_file(dir.join(format!("{stem}.bin"))).unwrap();
+        fs::remove_file(dir.join(format!("{stem}.gltf"))).unwrap();
+        instances
+    }
+
+    fn positions() -> Vec<u8> {
+        [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn triangle_loads() {
+        let instances = load("triangle", |_| {}, &positions()).unwrap();
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].0.verts.len(), 3);
+        assert_eq!(instances[0].0.normals.len(), 3);
+    }
+
+    #[test]
+    fn short_attribute_is_rejected() {
+        let short_normals = |doc: &mut Value| {
+            doc["accessors"]
+                .as_array_mut()
+                .unwrap()
+                .push(json!({ "componentType": 5126, "count": 2, "type": "VEC3" }));
+            doc["meshes"][0]["primitives"][0]["attributes"]["NORMAL"] = json!(1);
+        };
+        assert!(load("short-normals", short_normals, &positions()).is_err());
+    }
+
+    #[test]
+    fn truncated_buffer_is_rejected() {
+        assert!(load("truncated", |_| {}, &positions()[..24]).is_err());
+    }
+
+    #[test]
+    fn node_cycle_is_rejected() {
+        let cycle = |doc: &mut Value| {
+            doc["nodes"] = json!([{ "mesh": 0, "children": [1] }, { "children": [0] }]);
+        };
+        assert!(load("cycle", cycle, &positions()).is_err());
+    }
+}
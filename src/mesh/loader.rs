@@ -1,9 +1,11 @@
 use image::{DynamicImage, ImageBuffer};
 use indicatif::{ProgressBar, ProgressState, ProgressStyle};
-use log::info;
+use log::{info, warn};
 use nalgebra::{Point2, Point3, Vector3};
+use rayon::prelude::*;
 
-use crate::mesh::{Color, Material, Mesh, Normal, SKYBLUE, TextureCoord, Triangle, Vertex};
+use crate::mesh::cache;
+use crate::mesh::{Color, Material, Mesh, Normal, SKYBLUE, TextureCoord, Triangle, Vertex, WHITE};
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::Write;
@@ -16,18 +18,64 @@ use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct GenericMesh {
-    verts: Vec<Vertex>,
-    tris: Vec<Triangle>,
-    texture_coords: Vec<TextureCoord>,
-    normals: Vec<Normal>,
+    pub(crate) verts: Vec<Vertex>,
+    pub(crate) tris: Vec<Triangle>,
+    pub(crate) texture_coords: Vec<TextureCoord>,
+    pub(crate) normals: Vec<Normal>,
+    pub(crate) colors: Vec<Color>,
 }
 
 impl GenericMesh {
+    /// Load an OBJ, reusing the binary cache next to it when neither the OBJ nor
+    /// any of its MTLs or textures changed since the cache was written.
     pub fn from_file(file_name: &str) -> Result<Self, Box<dyn Error>> {
+        let cache_path = cache::cache_path(file_name);
+        match GenericMesh::read_cache(&cache_path) {
+            Ok(Some(mesh)) => {
+                info!("Loaded cached mesh from {cache_path:?}");
+                return Ok(mesh);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Ignoring unreadable mesh cache {cache_path:?}: {e}"),
+        }
+
+        let (mesh, mtl_files) = GenericMesh::parse_obj(file_name)?;
+        let mut sources = vec![PathBuf::from(file_name)];
+        sources.extend(mtl_files);
+        for tri in &mesh.tris {
+            for texture in [
+                &tri.mtl.map_ka_path,
+                &tri.mtl.map_kd_path,
+                &tri.mtl.map_ks_path,
+                &tri.mtl.map_ke_path,
+                &tri.mtl.map_norm_path,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                if !sources.contains(texture) {
+                    sources.push(texture.clone());
+                }
+            }
+        }
+        if let Err(e) = mesh.write_cache(&cache_path, &sources) {
+            warn!("Couldn't write mesh cache {cache_path:?}: {e}");
+        }
+        Ok(mesh)
+    }
+
+    /// Parse an OBJ without reading or writing the mesh cache.
+    pub fn from_obj(file_name: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(GenericMesh::parse_obj(file_name)?.0)
+    }
+
+    /// Parse an OBJ, also returning the MTL files it pulled in.
+    fn parse_obj(file_name: &str) -> Result<(Self, Vec<PathBuf>), Box<dyn Error>> {
         let mut verts: Vec<Point3<f32>> = vec![];
         let mut normals: Vec<Vector3<f32>> = vec![];
         let mut tris: Vec<Triangle> = vec![];
         let mut texture_coords: Vec<TextureCoord> = vec![];
+        let mut colors: Vec<Color> = vec![];
 
         let mut mtl_map: HashMap<String, Arc<Material>> = HashMap::new();
         mtl_map.insert(
@@ -35,12 +83,26 @@ impl GenericMesh {
             Arc::new(Default::default()),
         );
         let mut cur_mtl = "\x04\x06__default__\x05";
+        let mut mtl_files: Vec<PathBuf> = vec![];
 
-        let file = File::open(file_name).map_err(|e| format!("Couldn't open file: {file_name}"))?;
+        let contents =
+            read_to_string(file_name).map_err(|e| format!("Couldn't open file: {file_name}"))?;
         let mut obj_dir = PathBuf::from(file_name);
         obj_dir.pop();
-        let reader = BufReader::new(file);
-        let total_lines = reader.lines().count();
+
+        // Split on line boundaries and number each chunk's first line up front so
+        // errors report the same line numbers as a single-threaded parse would.
+        let chunks = split_lines(&contents, PARALLEL_CHUNK_BYTES);
+        let line_counts: Vec<usize> = chunks
+            .par_iter()
+            .map(|chunk| chunk.lines().count())
+            .collect();
+        let mut first_lines = Vec::with_capacity(chunks.len());
+        let mut total_lines = 0;
+        for count in &line_counts {
+            first_lines.push(total_lines);
+            total_lines += count;
+        }
 
         let pb = ProgressBar::new(total_lines as u64);
         pb.set_style(
@@ -55,254 +117,80 @@ impl GenericMesh {
         );
         pb.set_message(file_name.to_owned());
 
-        for (lineno, line) in read_to_string(file_name)?.lines().enumerate() {
-            pb.set_position(lineno as u64);
-            let mut components = line.split_whitespace();
-            match components.next() {
-                Some("mtllib") => {
-                    let mtl_filename = components
-                        .next()
-                        .ok_or(format!("Missing mtl filename at line: {}", lineno + 1))?;
-                    let mut mtl_path = obj_dir.clone();
-                    mtl_path.push(mtl_filename);
-                    GenericMesh::parse_mtl(&mtl_path, &mut mtl_map)?
-                }
-                Some("usemtl") => {
-                    cur_mtl = components
-                        .next()
-                        .ok_or(format!("Missing material name at line: {}", lineno + 1))?;
-                }
-                Some("vt") => {
-                    let u: f32 = components
-                        .next()
-                        .ok_or(format!(
-                            "Missing first texture component at line: {}",
-                            lineno + 1
-                        ))?
-                        .parse()
-                        .map_err(|e| format!("Invalid f32 for u at line: {}", lineno + 1))?;
-                    let v: f32 = match components.next() {
-                        Some(v) => v
-                            .parse()
-                            .map_err(|e| format!("Invalid f32 for v at line: {}", lineno + 1))?,
-                        None => 0.0,
-                    };
-                    let w: f32 = match components.next() {
-                        Some(w) => w
-                            .parse()
-                            .map_err(|e| format!("Invalid f32 for w at line: {}", lineno + 1))?,
-                        None => 0.0,
-                    };
-                    texture_coords.push(TextureCoord { u, v, w });
-                }
-                Some("v") => {
-                    verts.push(Point3::new(
-                        components
-                            .next()
-                            .ok_or(format!(
-                                "Missing first vertex component at line: {}",
-                                lineno + 1
-                            ))?
-                            .parse()
-                            .map_err(|e| {
-                                format!("Invalid first digit for vertex at line: {}", lineno + 1)
-                            })?,
-                        components
-                            .next()
-                            .ok_or(format!(
-                                "Missing second vertex component at line: {}",
-                                lineno + 1
-                            ))?
-                            .parse()
-                            .map_err(|e| {
-                                format!("Invalid second digit for vertex at line: {}", lineno + 1)
-                            })?,
-                        components
-                            .next()
-                            .ok_or(format!(
-                                "Missing third vertex component at line: {}",
-                                lineno + 1
-                            ))?
-                            .parse()
-                            .map_err(|e| {
-                                format!("Invalid third digit for vertex at line: {}", lineno + 1)
-                            })?,
-                    ));
-                }
-                Some("vn") => {
-                    normals.push(Vector3::new(
-                        components
-                            .next()
-                            .ok_or(format!(
-                                "Missing first normal component at line: {}",
-                                lineno + 1
-                            ))?
-                            .parse()
-                            .map_err(|e| {
-                                format!("Invalid digit for normal at line: {}", lineno + 1)
-                            })?,
-                        components
-                            .next()
-                            .ok_or(format!(
-                                "Missing second normal component at line: {}",
-                                lineno + 1
-                            ))?
-                            .parse()
-                            .map_err(|e| {
-                                format!("Invalid digit for normal at line: {}", lineno + 1)
-                            })?,
-                        components
-                            .next()
-                            .ok_or(format!(
-                                "Missing third normal component at line: {}",
-                                lineno + 1
-                            ))?
-                            .parse()
-                            .map_err(|e| {
-                                format!("Invalid digit for normal at line: {}", lineno + 1)
-                            })?,
-                    ));
-                }
-                Some("f") => {
-                    let mut poly_verts: Vec<(i32, i32, i32)> = vec![];
-
-                    for _ in 0..2 {
-                        let mut face_iter = components
-                            .next()
-                            .ok_or(format!("Missing vertex at line: {}", lineno + 1))?
-                            .split("/");
-                        poly_verts.push((
-                            face_iter
-                                .next()
-                                .ok_or(format!("Missing vertex value at line: {}", lineno + 1))?
-                                .parse::<i32>()
-                                .map_err(|e| {
-                                    format!("Invalid vertex index digit at line: {}", lineno + 1)
-                                })?
-                                - 1,
-                            match face_iter.next() {
-                                Some(vt) => {
-                                    if vt.is_empty() {
-                                        0
-                                    } else {
-                                        vt.parse::<i32>().map_err(|e| {
-                                            format!(
-                                                "Invalid texture index digit at line: {}",
-                                                lineno + 1
-                                            )
-                                        })? - 1
-                                    }
-                                }
-                                None => 0,
-                            },
-                            match face_iter.next() {
-                                Some(vn) => {
-                                    if vn.is_empty() {
-                                        0
-                                    } else {
-                                        vn.parse::<i32>().map_err(|e| {
-                                            format!(
-                                                "Invalid normal index digit at line: {}",
-                                                lineno + 1
-                                            )
-                                        })?
-                                    }
-                                }
-                                None => 0,
-                            },
-                        ));
-                    }
+        let parsed: Vec<ObjChunk> = chunks
+            .par_iter()
+            .zip(first_lines.par_iter())
+            .zip(line_counts.par_iter())
+            .map(|((chunk, first_line), line_count)| {
+                let parsed = ObjChunk::parse(chunk, *first_line);
+                pb.inc(*line_count as u64);
+                parsed
+            })
+            .collect();
 
-                    // We ensure that every polygon has at LEAST three verticies.
-                    // Now collect the rest :)
-                    for comp in components {
-                        let mut face_iter = comp.split("/");
-                        poly_verts.push((
-                            face_iter
-                                .next()
-                                .ok_or(format!("Missing vertex value at line: {}", lineno + 1))?
-                                .parse::<i32>()
-                                .map_err(|e| {
-                                    format!("Invalid vertex index digit at line: {}", lineno + 1)
-                                })?
-                                - 1,
-                            match face_iter.next() {
-                                Some(vt) => {
-                                    if vt.is_empty() {
-                                        0
-                                    } else {
-                                        vt.parse::<i32>().map_err(|e| {
-                                            format!(
-                                                "Invalid texture index digit at line: {}",
-                                                lineno + 1
-                                            )
-                                        })? - 1
-                                    }
-                                }
-                                None => 0,
-                            },
-                            match face_iter.next() {
-                                Some(vn) => {
-                                    if vn.is_empty() {
-                                        0
-                                    } else {
-                                        vn.parse::<i32>().map_err(|e| {
-                                            format!(
-                                                "Invalid normal index digit at line: {}",
-                                                lineno + 1
-                                            )
-                                        })?
-                                    }
-                                }
-                                None => 0,
-                            },
-                        ));
+        // Merge step: everything that depends on earlier lines (material
+        // libraries, the active material) is resolved here in file order.
+        for chunk in parsed {
+            // Colors stay empty until some vertex has one, then earlier vertices are white.
+            if !chunk.colors.is_empty() || !colors.is_empty() {
+                colors.resize(verts.len(), WHITE);
+                colors.extend(chunk.colors);
+            }
+            verts.extend(chunk.verts);
+            normals.extend(chunk.normals);
+            texture_coords.extend(chunk.texture_coords);
+            for (lineno, statement) in chunk.statements {
+                match statement {
+                    ObjStatement::MtlLib(mtl_filename) => {
+                        let mut mtl_path = obj_dir.clone();
+                        mtl_path.push(mtl_filename);
+                        GenericMesh::parse_mtl(&mtl_path, &mut mtl_map)?;
+                        mtl_files.push(mtl_path);
                     }
+                    ObjStatement::UseMtl(mtl_name) => {
+                        cur_mtl = mtl_name;
+                    }
+                    ObjStatement::Face(mut poly_verts) => {
+                        let material = mtl_map
+                            .get(cur_mtl)
+                            .ok_or(format!(
+                                "Couldn't find material at line: {} in file: {}",
+                                lineno + 1,
+                                file_name
+                            ))?
+                            .clone();
 
-                    let material = mtl_map
-                        .get(cur_mtl)
-                        .ok_or(format!(
-                            "Couldn't find material at line: {} in file: {}",
-                            lineno + 1,
-                            file_name
-                        ))?
-                        .clone();
-
-                    for tri in clip_ears(&mut poly_verts, material) {
-                        tris.push(tri);
+                        for tri in clip_ears(&mut poly_verts, material) {
+                            tris.push(tri);
+                        }
                     }
                 }
-                _ => continue,
+            }
+            if let Some(e) = chunk.error {
+                return Err(e.into());
             }
         }
 
         // IF they did not specify normals, we average over faces.
         if normals.is_empty() {
-            normals.resize(verts.len(), Vector3::zeros());
-            for triangle in &tris {
-                let i0 = triangle.verts[0];
-                let i1 = triangle.verts[1];
-                let i2 = triangle.verts[2];
-                let v0 = verts[i0];
-                let v1 = verts[i1];
-                let v2 = verts[i2];
-                let edge1 = v1 - v0;
-                let edge2 = v2 - v0;
-                let face_normal = edge1.cross(&edge2).normalize();
-
-                normals[i0] += face_normal;
-                normals[i1] += face_normal;
-                normals[i2] += face_normal;
-            }
+            normals = vertex_normals(&verts, &tris);
+        } else {
+            normals = normals.par_iter().map(|norm| norm.normalize()).collect();
+        }
+        if !colors.is_empty() {
+            colors.resize(verts.len(), WHITE);
         }
-        normals = normals.iter().map(|norm| norm.normalize()).collect();
         pb.finish();
-        Ok(Self {
-            verts,
-            tris,
-            texture_coords,
-            normals,
-        })
+        Ok((
+            Self {
+                verts,
+                tris,
+                texture_coords,
+                normals,
+                colors,
+            },
+            mtl_files,
+        ))
     }
 
     fn parse_mtl(
@@ -325,6 +213,7 @@ impl GenericMesh {
                         lineno + 1,
                         file_name
                     ))?;
+                    cur_mtl.name = String::from(cur_mtl_name);
                 }
                 Some("Ka") => {
                     cur_mtl.ka = color_from_line(&mut components, lineno + 1, file_name)?;
@@ -335,36 +224,48 @@ impl GenericMesh {
                 Some("Ks") => {
                     cur_mtl.ks = color_from_line(&mut components, lineno + 1, file_name)?;
                 }
-                Some("d") | Some("Tr") => {
-                    cur_mtl.transparency = components
-                        .next()
-                        .ok_or(format!(
-                            "Missing transparency at line: {} in file {:?}",
-                            lineno + 1,
-                            file_name
-                        ))?
-                        .parse::<f32>()
-                        .map_err(|e| format!("Invalid float at line: {}", lineno + 1))?;
+                Some("Ke") => {
+                    cur_mtl.ke = color_from_line(&mut components, lineno + 1, file_name)?;
+                }
+                Some("d") => {
+                    cur_mtl.transparency = float_from_line(&mut components, lineno, file_name)?;
+                }
+                // Tr is the inverse of d, 0 meaning fully opaque.
+                Some("Tr") => {
+                    cur_mtl.transparency =
+                        1.0 - float_from_line(&mut components, lineno, file_name)?;
                 }
                 Some("Tf") => {
                     // Not supported TODO later
                     continue;
                 }
                 Some("Ni") => {
-                    // Not supported TODO later
-                    continue;
+                    cur_mtl.ni = Some(float_from_line(&mut components, lineno, file_name)?);
                 }
                 Some("map_Ka") => {
-                    cur_mtl.map_ka =
-                        Some(open_image_from_line(&mut components, lineno, file_name)?);
+                    let (path, image) = open_image_from_line(&mut components, lineno, file_name)?;
+                    cur_mtl.map_ka = Some(image);
+                    cur_mtl.map_ka_path = Some(path);
                 }
                 Some("map_Kd") => {
-                    cur_mtl.map_kd =
-                        Some(open_image_from_line(&mut components, lineno, file_name)?);
+                    let (path, image) = open_image_from_line(&mut components, lineno, file_name)?;
+                    cur_mtl.map_kd = Some(image);
+                    cur_mtl.map_kd_path = Some(path);
                 }
                 Some("map_Ks") => {
-                    cur_mtl.map_ks =
-                        Some(open_image_from_line(&mut components, lineno, file_name)?);
+                    let (path, image) = open_image_from_line(&mut components, lineno, file_name)?;
+                    cur_mtl.map_ks = Some(image);
+                    cur_mtl.map_ks_path = Some(path);
+                }
+                Some("map_Ke") => {
+                    let (path, image) = open_image_from_line(&mut components, lineno, file_name)?;
+                    cur_mtl.map_ke = Some(image);
+                    cur_mtl.map_ke_path = Some(path);
+                }
+                Some("norm") => {
+                    let (path, image) = open_image_from_line(&mut components, lineno, file_name)?;
+                    cur_mtl.map_norm = Some(image);
+                    cur_mtl.map_norm_path = Some(path);
                 }
                 _ => {
                     continue;
@@ -394,13 +295,17 @@ impl Mesh for GenericMesh {
     fn normals(&self) -> &[Normal] {
         &self.normals
     }
+
+    fn colors(&self) -> &[Color] {
+        &self.colors
+    }
 }
 
 fn open_image_from_line(
     components: &mut SplitWhitespace,
     lineno: usize,
     file_name: &Path,
-) -> Result<DynamicImage, Box<dyn Error>> {
+) -> Result<(PathBuf, DynamicImage), Box<dyn Error>> {
     let mut image_file = components.next().ok_or(format!(
         "Missing image filename at line: {} in file {:?}",
         lineno + 1,
@@ -411,13 +316,30 @@ fn open_image_from_line(
     image_with_path.pop();
     image_with_path.push(image_file);
 
-    image::open(image_with_path).map_err(|e| {
+    let image = image::open(&image_with_path).map_err(|e| {
         Box::<dyn Error>::from(format!(
             "Failed to open file at line: {} in file {:?}",
             lineno + 1,
             file_name
         ))
-    })
+    })?;
+    Ok((image_with_path, image))
+}
+
+fn float_from_line(
+    components: &mut SplitWhitespace,
+    lineno: usize,
+    file_name: &Path,
+) -> Result<f32, Box<dyn Error>> {
+    Ok(components
+        .next()
+        .ok_or(format!(
+            "Missing value at line: {} in file {:?}",
+            lineno + 1,
+            file_name
+        ))?
+        .parse::<f32>()
+        .map_err(|e| format!("Invalid float at line: {}", lineno + 1))?)
 }
 
 fn color_from_line(
@@ -457,6 +379,288 @@ fn color_from_line(
     })
 }
 
+/// Target size of each slice of an OBJ file handed to a parser thread. Files
+/// smaller than this are parsed as a single chunk.
+const PARALLEL_CHUNK_BYTES: usize = 1 << 20;
+
+/// OBJ statements whose meaning depends on what came before them in the file.
+enum ObjStatement<'a> {
+    MtlLib(&'a str),
+    UseMtl(&'a str),
+    Face(Vec<(i32, i32, i32)>),
+}
+
+/// Everything parsed out of one contiguous run of lines. Vertex data can be
+/// concatenated as-is, statements are replayed in order during the merge.
+struct ObjChunk<'a> {
+    verts: Vec<Vertex>,
+    normals: Vec<Normal>,
+    texture_coords: Vec<TextureCoord>,
+    /// Empty unless a vertex in the chunk has a color, then one per vertex so far.
+    colors: Vec<Color>,
+    statements: Vec<(usize, ObjStatement<'a>)>,
+    error: Option<String>,
+}
+
+impl<'a> ObjChunk<'a> {
+    /// Parse `chunk`, whose first line is line `first_line` (0 based) of the file.
+    /// Parsing stops at the first bad line, keeping everything before it so the
+    /// merge can surface errors in the same order a sequential parse would.
+    fn parse(chunk: &'a str, first_line: usize) -> Self {
+        let mut parsed = ObjChunk {
+            verts: vec![],
+            normals: vec![],
+            texture_coords: vec![],
+            colors: vec![],
+            statements: vec![],
+            error: None,
+        };
+        for (lineno, line) in chunk.lines().enumerate() {
+            if let Err(e) = parsed.parse_line(line, first_line + lineno) {
+                parsed.error = Some(e);
+                break;
+            }
+        }
+        parsed
+    }
+
+    fn parse_line(&mut self, line: &'a str, lineno: usize) -> Result<(), String> {
+        let mut components = line.split_whitespace();
+        match components.next() {
+            Some("mtllib") => {
+                let mtl_filename = components
+                    .next()
+                    .ok_or(format!("Missing mtl filename at line: {}", lineno + 1))?;
+                self.statements
+                    .push((lineno, ObjStatement::MtlLib(mtl_filename)));
+            }
+            Some("usemtl") => {
+                let mtl_name = components
+                    .next()
+                    .ok_or(format!("Missing material name at line: {}", lineno + 1))?;
+                self.statements
+                    .push((lineno, ObjStatement::UseMtl(mtl_name)));
+            }
+            Some("vt") => {
+                let u: f32 = components
+                    .next()
+                    .ok_or(format!(
+                        "Missing first texture component at line: {}",
+                        lineno + 1
+                    ))?
+                    .parse()
+                    .map_err(|e| format!("Invalid f32 for u at line: {}", lineno + 1))?;
+                let v: f32 = match components.next() {
+                    Some(v) => v
+                        .parse()
+                        .map_err(|e| format!("Invalid f32 for v at line: {}", lineno + 1))?,
+                    None => 0.0,
+                };
+                let w: f32 = match components.next() {
+                    Some(w) => w
+                        .parse()
+                        .map_err(|e| format!("Invalid f32 for w at line: {}", lineno + 1))?,
+                    None => 0.0,
+                };
+                self.texture_coords.push(TextureCoord { u, v, w });
+            }
+            Some("v") => {
+                self.verts.push(Point3::new(
+                    components
+                        .next()
+                        .ok_or(format!(
+                            "Missing first vertex component at line: {}",
+                            lineno + 1
+                        ))?
+                        .parse()
+                        .map_err(|e| {
+                            format!("Invalid first digit for vertex at line: {}", lineno + 1)
+                        })?,
+                    components
+                        .next()
+                        .ok_or(format!(
+                            "Missing second vertex component at line: {}",
+                            lineno + 1
+                        ))?
+                        .parse()
+                        .map_err(|e| {
+                            format!("Invalid second digit for vertex at line: {}", lineno + 1)
+                        })?,
+                    components
+                        .next()
+                        .ok_or(format!(
+                            "Missing third vertex component at line: {}",
+                            lineno + 1
+                        ))?
+                        .parse()
+                        .map_err(|e| {
+                            format!("Invalid third digit for vertex at line: {}", lineno + 1)
+                        })?,
+                ));
+                // Vertex colors are a common extension, written as `v x y z r g b`.
+                // Anything else after the position (like a `w`) is still ignored.
+                if let [r, g, b] = components.collect::<Vec<_>>()[..] {
+                    let channel = |value: &str| {
+                        value.parse().map_err(|e| {
+                            format!("Invalid digit for vertex color at line: {}", lineno + 1)
+                        })
+                    };
+                    let color = Color {
+                        r: channel(r)?,
+                        g: channel(g)?,
+                        b: channel(b)?,
+                        a: 1.0,
+                    };
+                    self.colors.resize(self.verts.len() - 1, WHITE);
+                    self.colors.push(color);
+                } else if !self.colors.is_empty() {
+                    self.colors.push(WHITE);
+                }
+            }
+            Some("vn") => {
+                self.normals.push(Vector3::new(
+                    components
+                        .next()
+                        .ok_or(format!(
+                            "Missing first normal component at line: {}",
+                            lineno + 1
+                        ))?
+                        .parse()
+                        .map_err(|e| format!("Invalid digit for normal at line: {}", lineno + 1))?,
+                    components
+                        .next()
+                        .ok_or(format!(
+                            "Missing second normal component at line: {}",
+                            lineno + 1
+                        ))?
+                        .parse()
+                        .map_err(|e| format!("Invalid digit for normal at line: {}", lineno + 1))?,
+                    components
+                        .next()
+                        .ok_or(format!(
+                            "Missing third normal component at line: {}",
+                            lineno + 1
+                        ))?
+                        .parse()
+                        .map_err(|e| format!("Invalid digit for normal at line: {}", lineno + 1))?,
+                ));
+            }
+            Some("f") => {
+                let mut poly_verts: Vec<(i32, i32, i32)> = vec![];
+
+                for _ in 0..2 {
+                    let comp = components
+                        .next()
+                        .ok_or(format!("Missing vertex at line: {}", lineno + 1))?;
+                    poly_verts.push(face_vertex_from_component(comp, lineno)?);
+                }
+
+                // We ensure that every polygon has at LEAST three verticies.
+                // Now collect the rest :)
+                for comp in components {
+                    poly_verts.push(face_vertex_from_component(comp, lineno)?);
+                }
+
+                self.statements
+                    .push((lineno, ObjStatement::Face(poly_verts)));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Parse a `v/vt/vn` face component. Vertex and texture indices are made zero
+/// based, the normal index is left one based so 0 can mean "not given".
+fn face_vertex_from_component(comp: &str, lineno: usize) -> Result<(i32, i32, i32), String> {
+    let mut face_iter = comp.split("/");
+    Ok((
+        face_iter
+            .next()
+            .ok_or(format!("Missing vertex value at line: {}", lineno + 1))?
+            .parse::<i32>()
+            .map_err(|e| format!("Invalid vertex index digit at line: {}", lineno + 1))?
+            - 1,
+        match face_iter.next() {
+            Some(vt) => {
+                if vt.is_empty() {
+                    0
+                } else {
+                    vt.parse::<i32>().map_err(|e| {
+                        format!("Invalid texture index digit at line: {}", lineno + 1)
+                    })? - 1
+                }
+            }
+            None => 0,
+        },
+        match face_iter.next() {
+            Some(vn) => {
+                if vn.is_empty() {
+                    0
+                } else {
+                    vn.parse::<i32>().map_err(|e| {
+                        format!("Invalid normal index digit at line: {}", lineno + 1)
+                    })?
+                }
+            }
+            None => 0,
+        },
+    ))
+}
+
+/// Split `contents` into pieces of roughly `chunk_bytes`, only ever cutting
+/// directly after a newline.
+fn split_lines(contents: &str, chunk_bytes: usize) -> Vec<&str> {
+    let mut chunks = vec![];
+    let mut rest = contents;
+    while rest.len() > chunk_bytes {
+        let cut = match rest.as_bytes()[chunk_bytes..]
+            .iter()
+            .position(|byte| *byte == b'\n')
+        {
+            Some(newline) => chunk_bytes + newline + 1,
+            None => rest.len(),
+        };
+        let (chunk, tail) = rest.split_at(cut);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    if !rest.is_empty() {
+        chunks.push(rest);
+    }
+    chunks
+}
+
+/// Smooth per-vertex normals, averaged over the faces touching each vertex.
+pub(crate) fn vertex_normals(verts: &[Vertex], tris: &[Triangle]) -> Vec<Normal> {
+    let mut normals = vec![Vector3::zeros(); verts.len()];
+    for triangle in tris {
+        let i0 = triangle.verts[0];
+        let i1 = triangle.verts[1];
+        let i2 = triangle.verts[2];
+        let v0 = verts[i0];
+        let v1 = verts[i1];
+        let v2 = verts[i2];
+        let edge1 = v1 - v0;
+        let edge2 = v2 - v0;
+        // Slivers have no direction of their own, normalizing them gives NaN.
+        let Some(face_normal) = edge1.cross(&edge2).try_normalize(f32::EPSILON) else {
+            continue;
+        };
+
+        normals[i0] += face_normal;
+        normals[i1] += face_normal;
+        normals[i2] += face_normal;
+    }
+    normals
+        .par_iter()
+        .map(|norm| {
+            norm.try_normalize(f32::EPSILON)
+                .unwrap_or_else(Vector3::zeros)
+        })
+        .collect()
+}
+
 fn clip_ears(poly_verts: &mut Vec<(i32, i32, i32)>, cur_mtl: Arc<Material>) -> Vec<Triangle> {
     let mut tris: Vec<Triangle> = vec![];
     let mut first = 1;
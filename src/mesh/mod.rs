@@ -1,12 +1,25 @@
 #![allow(unused)]
+pub mod bounds;
+pub mod bvh;
+pub mod cache;
+pub mod export;
+pub mod gltf;
 pub mod loader;
+pub mod ply;
 pub mod premade;
+pub mod process;
+pub mod ray;
+pub mod simplify;
+pub mod stl;
+pub mod subdivide;
 
 use std::{
     ops::{Add, Mul},
+    path::PathBuf,
     sync::Arc,
 };
 
+use bounds::{Aabb, BoundingSphere};
 use image::DynamicImage;
 use nalgebra::{Point2, Point3, Vector3};
 
@@ -15,6 +28,18 @@ pub trait Mesh: Sync {
     fn tris(&self) -> &[Triangle];
     fn texturecoords(&self) -> &[TextureCoord];
     fn normals(&self) -> &[Normal];
+    /// Per-vertex colors, indexed like `verts`. Empty when the mesh has none.
+    fn colors(&self) -> &[Color] {
+        &[]
+    }
+
+    fn aabb(&self) -> Aabb {
+        Aabb::from_points(self.verts())
+    }
+
+    fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere::from_points(self.verts())
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -45,29 +70,50 @@ pub struct Color {
 
 #[derive(Clone, Debug)]
 pub struct Material {
+    pub name: String,
     pub ka: Color,
     pub kd: Color,
     pub ks: Color,
+    pub ke: Color,
+    /// Dissolve, as in the MTL `d` statement: 1 is fully opaque.
     pub transparency: f32,
     pub tf: Color,
-    pub ni: f32,
+    /// Optical density, only known when the file gave one.
+    pub ni: Option<f32>,
     pub map_ka: Option<DynamicImage>,
     pub map_kd: Option<DynamicImage>,
     pub map_ks: Option<DynamicImage>,
+    pub map_ke: Option<DynamicImage>,
+    /// Tangent space normal map.
+    pub map_norm: Option<DynamicImage>,
+    pub map_ka_path: Option<PathBuf>,
+    pub map_kd_path: Option<PathBuf>,
+    pub map_ks_path: Option<PathBuf>,
+    pub map_ke_path: Option<PathBuf>,
+    pub map_norm_path: Option<PathBuf>,
 }
 
 impl Default for Material {
     fn default() -> Self {
         Self {
+            name: String::new(),
             ka: DIM,
             kd: DIM,
             ks: DIM,
-            transparency: 0.0,
+            ke: BLACK,
+            transparency: 1.0,
             tf: BLACK,
-            ni: 0.0,
+            ni: None,
             map_ka: None,
             map_kd: None,
             map_ks: None,
+            map_ke: None,
+            map_norm: None,
+            map_ka_path: None,
+            map_kd_path: None,
+            map_ks_path: None,
+            map_ke_path: None,
+            map_norm_path: None,
         }
     }
 }
@@ -176,6 +222,12 @@ pub const LIME: Color = Color {
     b: 0.0,
     a: 1.0,
 };
+pub const WHITE: Color = Color {
+    r: 1.0,
+    g: 1.0,
+    b: 1.0,
+    a: 1.0,
+};
 pub const BLACK: Color = Color {
     r: 0.0,
     g: 0.0,
@@ -0,0 +1,484 @@
+use std::error::Error;
+use std::fs;
+use std::str::SplitWhitespace;
+use std::sync::Arc;
+
+use nalgebra::{Point3, Vector3};
+
+use crate::mesh::loader::{GenericMesh, vertex_normals};
+use crate::mesh::{Color, Material, TextureCoord, Triangle};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+#[derive(Debug)]
+enum PlyProperty {
+    Scalar {
+        name: String,
+        ty: PlyType,
+    },
+    List {
+        name: String,
+        count_ty: PlyType,
+        item_ty: PlyType,
+    },
+}
+
+#[derive(Debug)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+impl PlyType {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "char" | "int8" => Some(PlyType::I8),
+            "uchar" | "uint8" => Some(PlyType::U8),
+            "short" | "int16" => Some(PlyType::I16),
+            "ushort" | "uint16" => Some(PlyType::U16),
+            "int" | "int32" => Some(PlyType::I32),
+            "uint" | "uint32" => Some(PlyType::U32),
+            "float" | "float32" => Some(PlyType::F32),
+            "double" | "float64" => Some(PlyType::F64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyType::I8 | PlyType::U8 => 1,
+            PlyType::I16 | PlyType::U16 => 2,
+            PlyType::I32 | PlyType::U32 | PlyType::F32 => 4,
+            PlyType::F64 => 8,
+        }
+    }
+
+    /// Scale factor that maps this type's color channel range onto 0..1.
+    fn color_scale(self) -> f32 {
+        match self {
+            PlyType::U8 | PlyType::I8 => 1.0 / 255.0,
+            PlyType::U16 | PlyType::I16 => 1.0 / 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+/// Reads the values following the header, one property at a time.
+enum PlyBody<'a> {
+    Ascii(SplitWhitespace<'a>),
+    Binary {
+        bytes: &'a [u8],
+        pos: usize,
+        big_endian: bool,
+    },
+}
+
+impl PlyBody<'_> {
+    fn read(&mut self, ty: PlyType) -> Result<f64, String> {
+        match self {
+            PlyBody::Ascii(tokens) => tokens
+                .next()
+                .ok_or("Unexpected end of PLY data")?
+                .parse::<f64>()
+                .map_err(|e| format!("Invalid PLY value: {e}")),
+            PlyBody::Binary {
+                bytes,
+                pos,
+                big_endian,
+            } => {
+                let raw = bytes
+                    .get(*pos..*pos + ty.size())
+                    .ok_or("Unexpected end of PLY data")?;
+                *pos += ty.size();
+                let mut buf = [0u8; 8];
+                buf[..raw.len()].copy_from_slice(raw);
+                if *big_endian {
+                    buf[..raw.len()].reverse();
+                }
+                Ok(match ty {
+                    PlyType::I8 => i8::from_le_bytes([buf[0]]) as f64,
+                    PlyType::U8 => buf[0] as f64,
+                    PlyType::I16 => i16::from_le_bytes([buf[0], buf[1]]) as f64,
+                    PlyType::U16 => u16::from_le_bytes([buf[0], buf[1]]) as f64,
+                    PlyType::I32 => i32::from_le_bytes(buf[..4].try_into().unwrap()) as f64,
+                    PlyType::U32 => u32::from_le_bytes(buf[..4].try_into().unwrap()) as f64,
+                    PlyType::F32 => f32::from_le_bytes(buf[..4].try_into().unwrap()) as f64,
+                    PlyType::F64 => f64::from_le_bytes(buf),
+                })
+            }
+        }
+    }
+}
+
+impl GenericMesh {
+    /// Load an ASCII or binary (either endianness) PLY. Positions, normals,
+    /// texture coordinates and colors are read from the `vertex` element, polygons
+    /// from the `face` element. Elements we don't use are skipped.
+    pub fn from_ply(file_name: &str) -> Result<Self, Box<dyn Error>> {
+        let bytes = fs::read(file_name).map_err(|e| format!("Couldn't open file: {file_name}"))?;
+        let (format, elements, body_start) = parse_header(&bytes)?;
+        let mut body = match format {
+            PlyFormat::Ascii => {
+                PlyBody::Ascii(std::str::from_utf8(&bytes[body_start..])?.split_whitespace())
+            }
+            PlyFormat::BinaryLittleEndian | PlyFormat::BinaryBigEndian => PlyBody::Binary {
+                bytes: &bytes[body_start..],
+                pos: 0,
+                big_endian: format == PlyFormat::BinaryBigEndian,
+            },
+        };
+
+        let material: Arc<Material> = Arc::new(Default::default());
+        let mut verts = vec![];
+        let mut normals = vec![];
+        let mut texture_coords = vec![];
+        let mut colors = vec![];
+        let mut tris = vec![];
+
+        for element in &elements {
+            match element.name.as_str() {
+                "vertex" => {
+                    let index_of = |names: &[&str]| {
+                        element.properties.iter().position(|prop| {
+                            matches!(prop, PlyProperty::Scalar { name, .. } if names.contains(&name.as_str()))
+                        })
+                    };
+                    let position = [index_of(&["x"]), index_of(&["y"]), index_of(&["z"])];
+                    let normal = [index_of(&["nx"]), index_of(&["ny"]), index_of(&["nz"])];
+                    let uv = [
+                        index_of(&["u", "s", "texture_u", "texture_s"]),
+                        index_of(&["v", "t", "texture_v", "texture_t"]),
+                    ];
+                    let color = [
+                        index_of(&["red", "r", "diffuse_red"]),
+                        index_of(&["green", "g", "diffuse_green"]),
+                        index_of(&["blue", "b", "diffuse_blue"]),
+                    ];
+                    let alpha = index_of(&["alpha", "a", "diffuse_alpha"]);
+                    let [Some(x), Some(y), Some(z)] = position else {
+                        return Err(
+                            format!("PLY vertices have no x/y/z in file {file_name}").into()
+                        );
+                    };
+
+                    let mut values = vec![0.0; element.properties.len()];
+                    for _ in 0..element.count {
+                        read_element(&mut body, element, &mut values)?;
+                        verts.push(Point3::new(
+                            values[x] as f32,
+                            values[y] as f32,
+                            values[z] as f32,
+                        ));
+                        if let [Some(nx), Some(ny), Some(nz)] = normal {
+                            normals.push(Vector3::new(
+                                values[nx] as f32,
+                                values[ny] as f32,
+                                values[nz] as f32,
+                            ));
+                        }
+                        if let [Some(u), Some(v)] = uv {
+                            texture_coords.push(TextureCoord {
+                                u: values[u] as f32,
+                                v: values[v] as f32,
+                                w: 0.0,
+                            });
+                        }
+                        if let [Some(r), Some(g), Some(b)] = color {
+                            let channel = |index: usize| {
+                                values[index] as f32
+                                    * scalar_type(&element.properties[index]).color_scale()
+                            };
+                            colors.push(Color {
+                                r: channel(r),
+                                g: channel(g),
+                                b: channel(b),
+                                a: alpha.map_or(1.0, channel),
+                            });
+                        }
+                    }
+                }
+                "face" => {
+                    let indices = element.properties.iter().position(|prop| {
+                        matches!(prop, PlyProperty::List { name, .. } if name == "vertex_indices" || name == "vertex_index")
+                    });
+                    let Some(indices) = indices else {
+                        return Err(format!(
+                            "PLY faces have no vertex_indices in file {file_name}"
+                        )
+                        .into());
+                    };
+                    for _ in 0..element.count {
+                        for (i, prop) in element.properties.iter().enumerate() {
+                            match prop {
+                                PlyProperty::Scalar { ty, .. } => {
+                                    body.read(*ty)?;
+                                }
+                                PlyProperty::List {
+                                    count_ty, item_ty, ..
+                                } => {
+                                    let count = body.read(*count_ty)? as usize;
+                                    // The count comes straight from the file, so let
+                                    // the list grow as items are actually read.
+                                    let mut poly = vec![];
+                                    for _ in 0..count {
+                                        let value = body.read(*item_ty)?;
+                                        // Casting would quietly turn a negative index into 0.
+                                        if i == indices && (value < 0.0 || value.is_nan()) {
+                                            return Err(format!(
+                                                "PLY face references vertex {value} in file {file_name}"
+                                            )
+                                            .into());
+                                        }
+                                        poly.push(value as usize);
+                                    }
+                                    if i == indices {
+                                        fan_triangulate(&poly, &material, &mut tris);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                _ => {
+                    let mut values = vec![0.0; element.properties.len()];
+                    for _ in 0..element.count {
+                        read_element(&mut body, element, &mut values)?;
+                    }
+                }
+            }
+        }
+
+        if let Some(tri) = tris
+            .iter()
+            .find(|tri| tri.verts.iter().any(|vert| *vert >= verts.len()))
+        {
+            return Err(format!(
+                "PLY face references vertex {} but there are only {} in file {file_name}",
+                tri.verts.iter().max().unwrap(),
+                verts.len()
+            )
+            .into());
+        }
+
+        let normals = if normals.is_empty() {
+            vertex_normals(&verts, &tris)
+        } else {
+            normals
+                .iter()
+                .map(|norm: &Vector3<f32>| {
+                    norm.try_normalize(f32::EPSILON)
+                        .unwrap_or_else(Vector3::zeros)
+                })
+                .collect()
+        };
+
+        Ok(GenericMesh {
+            verts,
+            tris,
+            texture_coords,
+            normals,
+            colors,
+        })
+    }
+}
+
+/// Parse everything up to and including `end_header`, returning where the body starts.
+fn parse_header(bytes: &[u8]) -> Result<(PlyFormat, Vec<PlyElement>, usize), Box<dyn Error>> {
+    const END_HEADER: &[u8] = b"end_header";
+    let end = bytes
+        .windows(END_HEADER.len())
+        .position(|window| window == END_HEADER)
+        .ok_or("Missing end_header in PLY file")?;
+    let body_start = bytes[end..]
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .map_or(bytes.len(), |newline| end + newline + 1);
+    let header = std::str::from_utf8(&bytes[..end])?;
+
+    let mut lines = header.lines().enumerate();
+    if lines.next().map(|(_, line)| line.trim()) != Some("ply") {
+        return Err("Not a PLY file, missing magic number".into());
+    }
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = vec![];
+    for (lineno, line) in lines {
+        let mut components = line.split_whitespace();
+        match components.next() {
+            Some("format") => {
+                format = Some(match components.next() {
+                    Some("ascii") => PlyFormat::Ascii,
+                    Some("binary_little_endian") => PlyFormat::BinaryLittleEndian,
+                    Some("binary_big_endian") => PlyFormat::BinaryBigEndian,
+                    other => {
+                        return Err(format!(
+                            "Unknown PLY format {other:?} at line: {}",
+                            lineno + 1
+                        )
+                        .into());
+                    }
+                });
+            }
+            Some("element") => {
+                let name = components
+                    .next()
+                    .ok_or(format!("Missing element name at line: {}", lineno + 1))?;
+                let count = components
+                    .next()
+                    .ok_or(format!("Missing element count at line: {}", lineno + 1))?
+                    .parse()
+                    .map_err(|e| format!("Invalid element count at line: {}", lineno + 1))?;
+                elements.push(PlyElement {
+                    name: String::from(name),
+                    count,
+                    properties: vec![],
+                });
+            }
+            Some("property") => {
+                let element = elements.last_mut().ok_or(format!(
+                    "Property before any element at line: {}",
+                    lineno + 1
+                ))?;
+                let property = match components.next() {
+                    Some("list") => PlyProperty::List {
+                        count_ty: type_from_component(components.next(), lineno)?,
+                        item_ty: type_from_component(components.next(), lineno)?,
+                        name: property_name(components.next(), lineno)?,
+                    },
+                    ty => PlyProperty::Scalar {
+                        ty: type_from_component(ty, lineno)?,
+                        name: property_name(components.next(), lineno)?,
+                    },
+                };
+                element.properties.push(property);
+            }
+            _ => continue,
+        }
+    }
+
+    Ok((
+        format.ok_or("Missing format line in PLY header")?,
+        elements,
+        body_start,
+    ))
+}
+
+fn type_from_component(component: Option<&str>, lineno: usize) -> Result<PlyType, String> {
+    component
+        .and_then(PlyType::from_name)
+        .ok_or(format!("Invalid property type at line: {}", lineno + 1))
+}
+
+fn property_name(component: Option<&str>, lineno: usize) -> Result<String, String> {
+    component
+        .map(String::from)
+        .ok_or(format!("Missing property name at line: {}", lineno + 1))
+}
+
+fn scalar_type(property: &PlyProperty) -> PlyType {
+    match property {
+        PlyProperty::Scalar { ty, .. } => *ty,
+        PlyProperty::List { item_ty, .. } => *item_ty,
+    }
+}
+
+/// Read one element into `values`, one slot per property. Lists are consumed
+/// and left as 0 since only faces care about them.
+fn read_element(
+    body: &mut PlyBody,
+    element: &PlyElement,
+    values: &mut [f64],
+) -> Result<(), String> {
+    for (value, prop) in values.iter_mut().zip(&element.properties) {
+        match prop {
+            PlyProperty::Scalar { ty, .. } => *value = body.read(*ty)?,
+            PlyProperty::List {
+                count_ty, item_ty, ..
+            } => {
+                for _ in 0..body.read(*count_ty)? as usize {
+                    body.read(*item_ty)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Split a convex polygon into a fan of triangles around its first vertex,
+/// wound the same way `clip_ears` winds OBJ faces. Texture coordinates and
+/// normals are per vertex in PLY, so they share the vertex indices.
+fn fan_triangulate(poly: &[usize], material: &Arc<Material>, tris: &mut Vec<Triangle>) {
+    for i in 1..poly.len().saturating_sub(1) {
+        let verts = [poly[i], poly[0], poly[i + 1]];
+        tris.push(Triangle {
+            verts,
+            texes: verts,
+            norms: [0; 3],
+            mtl: material.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLE: &str = "ply\nformat ascii 1.0\nelement vertex 3\n\
+        property float x\nproperty float y\nproperty float z\n\
+        element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+        0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n";
+
+    fn load(name: &str, contents: &[u8]) -> Result<GenericMesh, Box<dyn Error>> {
+        let path =
+            std::env::temp_dir().join(format!("engine-ply-{name}-{}.ply", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        let mesh = GenericMesh::from_ply(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        mesh
+    }
+
+    #[test]
+    fn ascii_triangle_loads() {
+        let mesh = load("ascii", TRIANGLE.as_bytes()).unwrap();
+        assert_eq!(mesh.verts.len(), 3);
+        assert_eq!(mesh.tris.len(), 1);
+    }
+
+    #[test]
+    fn negative_index_is_rejected() {
+        let broken = TRIANGLE.replace("3 0 1 2", "3 0 -1 2");
+        assert!(load("negative", broken.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn truncated_binary_is_rejected() {
+        let header = TRIANGLE
+            .replace("ascii", "binary_little_endian")
+            .split_once("end_header\n")
+            .unwrap()
+            .0
+            .to_owned()
+            + "end_header\n";
+        let mut bytes = header.into_bytes();
+        bytes.extend(1.0f32.to_le_bytes());
+        assert!(load("truncated", &bytes).is_err());
+    }
+}
@@ -0,0 +1,458 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use nalgebra::Vector3;
+
+use crate::mesh::Vertex;
+use crate::mesh::loader::{GenericMesh, vertex_normals};
+
+/// What [`GenericMesh::cleanup`] changed.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CleanupReport {
+    pub welded_verts: usize,
+    pub degenerate_tris: usize,
+    pub duplicate_tris: usize,
+    pub unused_verts: usize,
+    pub unused_normals: usize,
+    pub unused_texture_coords: usize,
+}
+
+impl fmt::Display for CleanupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "welded {} verts, removed {} degenerate and {} duplicate tris, dropped {} unused verts, {} normals and {} texture coords",
+            self.welded_verts,
+            self.degenerate_tris,
+            self.duplicate_tris,
+            self.unused_verts,
+            self.unused_normals,
+            self.unused_texture_coords
+        )
+    }
+}
+
+/// What [`GenericMesh::orient`] changed.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OrientReport {
+    pub components: usize,
+    pub flipped_tris: usize,
+    /// Components that were wound consistently but inside out.
+    pub inside_out_components: usize,
+    /// Edges two triangles still run along in the same direction, which only
+    /// happens on surfaces like a Möbius strip that have no consistent winding.
+    pub conflicting_edges: usize,
+}
+
+impl fmt::Display for OrientReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "flipped {} tris across {} connected components, {} of which were inside out, {} edges couldn't be made consistent",
+            self.flipped_tris, self.components, self.inside_out_components, self.conflicting_edges
+        )
+    }
+}
+
+impl GenericMesh {
+    /// Run every cleanup step: weld vertices within `epsilon`, drop degenerate
+    /// and duplicate triangles, then drop data no triangle references anymore.
+    pub fn cleanup(&mut self, epsilon: f32) -> CleanupReport {
+        let welded_verts = self.weld_vertices(epsilon);
+        let degenerate_tris = self.remove_degenerate_triangles(epsilon);
+        let duplicate_tris = self.remove_duplicate_triangles();
+        let (unused_verts, unused_normals, unused_texture_coords) = self.remove_unreferenced();
+        CleanupReport {
+            welded_verts,
+            degenerate_tris,
+            duplicate_tris,
+            unused_verts,
+            unused_normals,
+            unused_texture_coords,
+        }
+    }
+
+    /// Point every triangle at explicit normal indices. Triangles without them
+    /// fall back to the normal at their vertex index, which stops being right
+    /// as soon as vertices are merged or moved around.
+    pub(crate) fn pin_normals(&mut self) {
+        if self.normals.is_empty() {
+            return;
+        }
+        for tri in &mut self.tris {
+            if !tri.norms.iter().all(|norm| *norm > 0) {
+                tri.norms = tri.verts.map(|vert| vert + 1);
+            }
+        }
+    }
+
+    /// Merge vertices closer than `epsilon` to each other into the first one
+    /// seen, or only exactly equal ones when `epsilon` is 0. Returns how many
+    /// vertices were merged away. The merged vertices are left in place until
+    /// [`GenericMesh::remove_unreferenced`] runs.
+    pub fn weld_vertices(&mut self, epsilon: f32) -> usize {
+        self.pin_normals();
+        let remap = if epsilon == 0.0 {
+            self.exact_weld_targets()
+        } else {
+            self.weld_targets(epsilon)
+        };
+        for tri in &mut self.tris {
+            tri.verts = tri.verts.map(|vert| remap[vert]);
+        }
+        remap
+            .iter()
+            .enumerate()
+            .filter(|(index, target)| index != *target)
+            .count()
+    }
+
+    /// The vertex every vertex welds to when only identical positions merge.
+    /// Hashing the bits skips the grid, whose cells can't be made that small.
+    fn exact_weld_targets(&self) -> Vec<usize> {
+        let mut seen: HashMap<[u32; 3], usize> = HashMap::new();
+        self.verts
+            .iter()
+            .enumerate()
+            .map(|(index, vert)| {
+                // Adding zero turns -0 into 0, which compare equal but differ in bits.
+                let key = [vert.x, vert.y, vert.z].map(|coord| (coord + 0.0).to_bits());
+                *seen.entry(key).or_insert(index)
+            })
+            .collect()
+    }
+
+    /// The vertex every vertex welds to when positions within `epsilon` merge.
+    fn weld_targets(&self, epsilon: f32) -> Vec<usize> {
+        let cell_size = epsilon.max(f32::MIN_POSITIVE);
+        let cell_of = |coord: f32| (coord / cell_size).floor() as i64;
+
+        // Spatial hash with cells of `epsilon`, so only the 27 cells around a
+        // vertex can hold anything close enough to weld to.
+        let mut grid: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+        let mut remap: Vec<usize> = Vec::with_capacity(self.verts.len());
+        for (index, vert) in self.verts.iter().enumerate() {
+            let cell = [cell_of(vert.x), cell_of(vert.y), cell_of(vert.z)];
+            let mut target = None;
+            'search: for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        // A tiny epsilon can push cells to the ends of the i64 range.
+                        let neighbour = [
+                            cell[0].saturating_add(dx),
+                            cell[1].saturating_add(dy),
+                            cell[2].saturating_add(dz),
+                        ];
+                        for candidate in grid.get(&neighbour).into_iter().flatten() {
+                            if (self.verts[*candidate] - vert).norm() <= epsilon {
+                                target = Some(*candidate);
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+            }
+            match target {
+                Some(target) => remap.push(target),
+                None => {
+                    grid.entry(cell).or_default().push(index);
+                    remap.push(index);
+                }
+            }
+        }
+        remap
+    }
+
+    /// Drop triangles that use a vertex twice or whose area is at most
+    /// `epsilon` squared. Returns how many were dropped.
+    pub fn remove_degenerate_triangles(&mut self, epsilon: f32) -> usize {
+        let before = self.tris.len();
+        let verts = &self.verts;
+        self.tris.retain(|tri| {
+            let [a, b, c] = tri.verts;
+            if a == b || b == c || a == c {
+                return false;
+            }
+            let area = (verts[b] - verts[a]).cross(&(verts[c] - verts[a])).norm() * 0.5;
+            area > epsilon * epsilon
+        });
+        before - self.tris.len()
+    }
+
+    /// Drop triangles using the same three vertices in the same winding as an
+    /// earlier one. Oppositely wound copies are kept since they're how a
+    /// double-sided surface gets drawn. Returns how many were dropped.
+    pub fn remove_duplicate_triangles(&mut self) -> usize {
+        let before = self.tris.len();
+        let mut seen: HashSet<[usize; 3]> = HashSet::new();
+        self.tris.retain(|tri| {
+            // Rotate the smallest index to the front, which keeps the winding.
+            let [a, b, c] = tri.verts;
+            let key = if a <= b && a <= c {
+                [a, b, c]
+            } else if b <= c {
+                [b, c, a]
+            } else {
+                [c, a, b]
+            };
+            seen.insert(key)
+        });
+        before - self.tris.len()
+    }
+
+    /// Compact away vertices, normals and texture coordinates that no triangle
+    /// uses. Returns how many of each were removed.
+    pub fn remove_unreferenced(&mut self) -> (usize, usize, usize) {
+        self.pin_normals();
+        let tex_count = self.texture_coords.len();
+
+        let mut vert_used = vec![false; self.verts.len()];
+        let mut norm_used = vec![false; self.normals.len()];
+        let mut tex_used = vec![false; tex_count];
+        for tri in &self.tris {
+            for vert in tri.verts {
+                vert_used[vert] = true;
+            }
+            for norm in tri.norms {
+                if let Some(used) = norm.checked_sub(1).and_then(|norm| norm_used.get_mut(norm)) {
+                    *used = true;
+                }
+            }
+            for tex in tri.texes {
+                if let Some(used) = tex_used.get_mut(tex) {
+                    *used = true;
+                }
+            }
+        }
+
+        let vert_remap = compact(&mut self.verts, &vert_used);
+        if self.colors.len() == vert_used.len() {
+            compact(&mut self.colors, &vert_used);
+        }
+        let norm_remap = compact(&mut self.normals, &norm_used);
+        let tex_remap = compact(&mut self.texture_coords, &tex_used);
+
+        for tri in &mut self.tris {
+            tri.verts = tri.verts.map(|vert| vert_remap[vert]);
+            tri.norms = tri.norms.map(|norm| {
+                match norm.checked_sub(1).and_then(|norm| norm_remap.get(norm)) {
+                    Some(norm) => norm + 1,
+                    None => norm,
+                }
+            });
+            tri.texes = tri
+                .texes
+                .map(|tex| tex_remap.get(tex).copied().unwrap_or(tex));
+        }
+
+        (
+            vert_used.iter().filter(|used| !**used).count(),
+            norm_used.iter().filter(|used| !**used).count(),
+            tex_used.iter().filter(|used| !**used).count(),
+        )
+    }
+
+    /// Wind every triangle the same way as its neighbours, turn each connected
+    /// piece so it faces outward, then recompute the normals to match.
+    /// Triangles only count as neighbours when they share vertex indices, so
+    /// seams need welding first.
+    pub fn orient(&mut self) -> OrientReport {
+        let edges = self.edge_uses();
+
+        // Walk each connected component from its first triangle, deciding
+        // whether to flip every triangle reached so it runs along each shared
+        // edge the opposite way the triangle it was reached from does.
+        let mut flips: Vec<Option<bool>> = vec![None; self.tris.len()];
+        let mut components: Vec<Vec<usize>> = vec![];
+        for seed in 0..self.tris.len() {
+            if flips[seed].is_some() {
+                continue;
+            }
+            flips[seed] = Some(false);
+            let mut stack = vec![seed];
+            let mut members = vec![];
+            while let Some(tri) = stack.pop() {
+                members.push(tri);
+                let flipped = flips[tri] == Some(true);
+                for [a, b] in tri_edges(self.tris[tri].verts) {
+                    let forward = (a < b) != flipped;
+                    for (other, other_forward) in &edges[&[a.min(b), a.max(b)]] {
+                        if flips[*other].is_none() {
+                            flips[*other] = Some(*other_forward == forward);
+                            stack.push(*other);
+                        }
+                    }
+                }
+            }
+            components.push(members);
+        }
+
+        // Triangles are wound the opposite way round from the file, so a
+        // closed surface facing outward encloses negative volume.
+        let mut inside_out_components = 0;
+        for members in &components {
+            if self.signed_volume(members, &flips) > 0.0 {
+                inside_out_components += 1;
+                for tri in members {
+                    flips[*tri] = flips[*tri].map(|flip| !flip);
+                }
+            }
+        }
+
+        let mut flipped_tris = 0;
+        for (tri, flip) in self.tris.iter_mut().zip(&flips) {
+            if *flip == Some(true) {
+                tri.verts.swap(0, 1);
+                tri.texes.swap(0, 1);
+                tri.norms.swap(0, 1);
+                flipped_tris += 1;
+            }
+        }
+
+        let conflicting_edges = self
+            .edge_uses()
+            .values()
+            .filter(|uses| matches!(uses[..], [(_, first), (_, second)] if first == second))
+            .count();
+        self.recompute_normals();
+        OrientReport {
+            components: components.len(),
+            flipped_tris,
+            inside_out_components,
+            conflicting_edges,
+        }
+    }
+
+    /// Every triangle using each edge, keyed by its lower vertex index first,
+    /// and whether the triangle runs from the lower index to the higher one.
+    fn edge_uses(&self) -> HashMap<[usize; 2], Vec<(usize, bool)>> {
+        let mut edges: HashMap<[usize; 2], Vec<(usize, bool)>> = HashMap::new();
+        for (index, tri) in self.tris.iter().enumerate() {
+            for [a, b] in tri_edges(tri.verts) {
+                edges
+                    .entry([a.min(b), a.max(b)])
+                    .or_default()
+                    .push((index, a < b));
+            }
+        }
+        edges
+    }
+
+    /// Volume enclosed by the triangles `members`, as wound once `flips` is
+    /// applied, or 0 when it's too small to tell which way they face, as for
+    /// an open flat patch.
+    fn signed_volume(&self, members: &[usize], flips: &[Option<bool>]) -> f32 {
+        let corners = || {
+            members
+                .iter()
+                .flat_map(|tri| self.tris[*tri].verts.map(|vert| self.verts[vert]))
+        };
+        // Measuring around a point on the surface keeps the rounding error of
+        // a component far from the origin down.
+        let count = (members.len() * 3) as f32;
+        let center =
+            Vertex::from(corners().fold(Vector3::zeros(), |sum, vert| sum + vert.coords) / count);
+        let mut volume = 0.0;
+        let mut extent = 0.0f32;
+        for tri in members {
+            let [mut a, mut b, c] = self.tris[*tri].verts.map(|vert| self.verts[vert] - center);
+            if flips[*tri] == Some(true) {
+                std::mem::swap(&mut a, &mut b);
+            }
+            volume += a.dot(&b.cross(&c)) / 6.0;
+            extent = extent.max(a.amax()).max(b.amax()).max(c.amax());
+        }
+        if volume.abs() <= extent.powi(3) * 1e-6 {
+            return 0.0;
+        }
+        volume
+    }
+
+    /// Throw away the current normals and average new ones per vertex.
+    pub fn recompute_normals(&mut self) {
+        self.normals = vertex_normals(&self.verts, &self.tris);
+        for tri in &mut self.tris {
+            tri.norms = [0; 3];
+        }
+    }
+}
+
+/// The three edges of a triangle in winding order.
+fn tri_edges([a, b, c]: [usize; 3]) -> [[usize; 2]; 3] {
+    [[a, b], [b, c], [c, a]]
+}
+
+/// Keep only the items marked `used`, returning where each old index ended up.
+/// Unused items map to wherever the next used one went, they're never looked up.
+fn compact<T: Copy>(items: &mut Vec<T>, used: &[bool]) -> Vec<usize> {
+    let mut remap = Vec::with_capacity(used.len());
+    let mut next = 0;
+    for (index, keep) in used.iter().enumerate() {
+        remap.push(next);
+        if *keep {
+            items[next] = items[index];
+            next += 1;
+        }
+    }
+    items.truncate(next);
+    remap
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::mesh::Triangle;
+
+    fn mesh(verts: &[[f32; 3]]) -> GenericMesh {
+        let mtl = Arc::new(Default::default());
+        GenericMesh {
+            verts: verts
+                .iter()
+                .map(|[x, y, z]| Vertex::new(*x, *y, *z))
+                .collect(),
+            tris: (0..verts.len() / 3)
+                .map(|tri| Triangle {
+                    verts: [tri * 3, tri * 3 + 1, tri * 3 + 2],
+                    texes: [0; 3],
+                    norms: [0; 3],
+                    mtl: Arc::clone(&mtl),
+                })
+                .collect(),
+            texture_coords: vec![],
+            normals: vec![],
+            colors: vec![],
+        }
+    }
+
+    #[test]
+    fn exact_weld_merges_identical_positions_only() {
+        let mut mesh = mesh(&[
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [-0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0 + f32::EPSILON, 0.0],
+        ]);
+        assert_eq!(mesh.weld_vertices(0.0), 2);
+        assert_eq!(mesh.tris[1].verts, [0, 1, 5]);
+    }
+
+    #[test]
+    fn epsilon_weld_merges_across_cells() {
+        let mut mesh = mesh(&[
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            // In the cell next to its match but within epsilon, too far, and
+            // within epsilon in the same cell.
+            [-0.004, 0.0, 0.0],
+            [1.0, 0.0, 0.011],
+            [0.0, 1.005, 0.0],
+        ]);
+        assert_eq!(mesh.weld_vertices(0.01), 2);
+        assert_eq!(mesh.tris[1].verts, [0, 4, 2]);
+    }
+}
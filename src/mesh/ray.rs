@@ -0,0 +1,122 @@
+use nalgebra::{Matrix4, Point3, Vector3};
+
+use crate::mesh::bounds::Aabb;
+use crate::mesh::bvh::Bvh;
+use crate::mesh::{Mesh, Vertex};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    /// Not necessarily unit length, distances along the ray are in multiples of it.
+    pub direction: Vector3<f32>,
+}
+
+/// Which sides of a triangle a ray can hit. The front is the side the renderer
+/// draws, where the corners go clockwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Facing {
+    Both,
+    Front,
+    Back,
+}
+
+/// Where a ray hit a mesh.
+#[derive(Debug, Clone, Copy)]
+pub struct MeshHit {
+    /// Index into the mesh's triangles.
+    pub tri: usize,
+    /// Distance along the ray, in multiples of its direction.
+    pub distance: f32,
+    /// Weights of the triangle's three corners at the hit point.
+    pub barycentric: [f32; 3],
+}
+
+impl Ray {
+    pub fn at(&self, distance: f32) -> Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    /// The ray in the space `transform` maps into. Distances along it stay the
+    /// same, which is what lets hits in model space be compared across models.
+    pub fn transformed(&self, transform: &Matrix4<f32>) -> Ray {
+        Ray {
+            origin: transform.transform_point(&self.origin),
+            direction: transform.transform_vector(&self.direction),
+        }
+    }
+
+    /// Distance at which the ray enters the box, 0 when it starts inside.
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        let mut entry = 0.0f32;
+        let mut exit = f32::INFINITY;
+        for axis in 0..3 {
+            // Parallel to the slab, the ray is either always or never in it.
+            if self.direction[axis] == 0.0 {
+                if !(aabb.min[axis]..=aabb.max[axis]).contains(&self.origin[axis]) {
+                    return None;
+                }
+                continue;
+            }
+            let near = (aabb.min[axis] - self.origin[axis]) / self.direction[axis];
+            let far = (aabb.max[axis] - self.origin[axis]) / self.direction[axis];
+            entry = entry.max(near.min(far));
+            exit = exit.min(near.max(far));
+        }
+        (entry <= exit).then_some(entry)
+    }
+
+    /// Möller-Trumbore, hitting only the sides `facing` allows.
+    pub fn intersect_triangle(
+        &self,
+        [a, b, c]: [Vertex; 3],
+        facing: Facing,
+    ) -> Option<(f32, [f32; 3])> {
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = self.direction.cross(&edge2);
+        // Negative when the corners go clockwise seen from the ray's origin.
+        let det = edge1.dot(&p);
+        if det.abs() < f32::EPSILON * edge1.norm() * edge2.norm() {
+            return None;
+        }
+        match facing {
+            Facing::Front if det > 0.0 => return None,
+            Facing::Back if det < 0.0 => return None,
+            _ => {}
+        }
+        let inverse = 1.0 / det;
+        let to_origin = self.origin - a;
+        let u = to_origin.dot(&p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = to_origin.cross(&edge1);
+        let v = self.direction.dot(&q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = edge2.dot(&q) * inverse;
+        (distance >= 0.0).then_some((distance, [1.0 - u - v, u, v]))
+    }
+
+    /// Closest triangle of `mesh` along the ray, with `bvh` built over it.
+    pub fn intersect_mesh(&self, mesh: &dyn Mesh, bvh: &Bvh, facing: Facing) -> Option<MeshHit> {
+        let verts = mesh.verts();
+        let tris = mesh.tris();
+        let mut barycentric = [0.0; 3];
+        let (tri, distance) = bvh.intersect_ray(self, |tri, max| {
+            let (distance, weights) =
+                self.intersect_triangle(tris[tri].verts.map(|vert| verts[vert]), facing)?;
+            if distance >= max {
+                return None;
+            }
+            barycentric = weights;
+            Some(distance)
+        })?;
+        Some(MeshHit {
+            tri,
+            distance,
+            barycentric,
+        })
+    }
+}
@@ -0,0 +1,504 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::ops::AddAssign;
+use std::sync::Arc;
+
+use nalgebra::Vector3;
+use ordered_float::OrderedFloat;
+
+use crate::mesh::loader::GenericMesh;
+use crate::mesh::{Material, Normal, Triangle, Vertex};
+
+/// Below this cosine between a triangle's normal before and after a collapse
+/// the triangle is considered flipped and the collapse is rejected.
+const MIN_NORMAL_COS: f32 = 0.2;
+
+/// How much more moving off a border costs than moving off a face, per squared
+/// unit of border edge length.
+const BORDER_WEIGHT: f64 = 100.0;
+
+/// Texture coordinate index, normal index and material a triangle uses at one
+/// of its corners.
+type Wedge = (usize, usize, *const Material);
+
+/// Sum of squared distances to a set of planes, stored as the upper triangle of
+/// the symmetric 4x4 matrix from Garland and Heckbert.
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_point_normal(point: &Vertex, normal: &Normal) -> Self {
+        let (a, b, c) = (normal.x as f64, normal.y as f64, normal.z as f64);
+        let d = -(a * point.x as f64 + b * point.y as f64 + c * point.z as f64);
+        Quadric::from_plane(a, b, c, d)
+    }
+
+    fn from_plane(a: f64, b: f64, c: f64, d: f64) -> Self {
+        Quadric([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ])
+    }
+
+    fn scaled(mut self, factor: f64) -> Self {
+        for value in &mut self.0 {
+            *value *= factor;
+        }
+        self
+    }
+
+    fn error(&self, point: &Vertex) -> f64 {
+        let [aa, ab, ac, ad, bb, bc, bd, cc, cd, dd] = self.0;
+        let (x, y, z) = (point.x as f64, point.y as f64, point.z as f64);
+        let error = aa * x * x
+            + 2.0 * ab * x * y
+            + 2.0 * ac * x * z
+            + 2.0 * ad * x
+            + bb * y * y
+            + 2.0 * bc * y * z
+            + 2.0 * bd * y
+            + cc * z * z
+            + 2.0 * cd * z
+            + dd;
+        error.max(0.0)
+    }
+}
+
+impl AddAssign for Quadric {
+    fn add_assign(&mut self, other: Quadric) {
+        for (lhs, rhs) in self.0.iter_mut().zip(other.0) {
+            *lhs += rhs;
+        }
+    }
+}
+
+/// A candidate collapse of `from` onto `to`, tagged with the versions of both
+/// vertices when it was queued so stale entries can be skipped.
+type Candidate = (Reverse<OrderedFloat<f64>>, usize, usize, u32, u32);
+
+/// Working state of one simplification run.
+struct Simplifier<'a> {
+    verts: &'a [Vertex],
+    tris: &'a mut [Triangle],
+    alive: Vec<bool>,
+    /// Triangles around every vertex. Dead triangles are filtered out lazily.
+    vert_tris: Vec<Vec<usize>>,
+    quadrics: Vec<Quadric>,
+    /// Like `quadrics` but only the face planes, unweighted, so the error is
+    /// a sum of squared distances that bounds the distance to each plane.
+    face_quadrics: Vec<Quadric>,
+    versions: Vec<u32>,
+    heap: BinaryHeap<Candidate>,
+}
+
+impl GenericMesh {
+    /// Collapse edges in order of least quadric error until at most
+    /// `target_tris` triangles are left or no collapse remains that keeps every
+    /// vertex within `max_error` of the planes of all original faces merged
+    /// into it. Returns how many triangles were removed.
+    ///
+    /// Vertices only ever collapse onto a neighbour, so texture coordinates and
+    /// normals are carried over instead of interpolated. Vertices on UV or normal
+    /// seams, material boundaries and open edges only slide along them, and the
+    /// corners where those meet never move, which keeps the outlines intact.
+    pub fn simplify(&mut self, target_tris: usize, max_error: f32) -> usize {
+        let before = self.tris.len();
+        if before <= target_tris {
+            return 0;
+        }
+        self.pin_normals();
+
+        let max_cost = (max_error as f64).powi(2);
+        let mut simplifier = Simplifier::new(&self.verts, &mut self.tris);
+        let mut alive_tris = simplifier.alive.iter().filter(|alive| **alive).count();
+        while alive_tris > target_tris {
+            let Some((Reverse(cost), from, to, from_version, to_version)) = simplifier.heap.pop()
+            else {
+                break;
+            };
+            if simplifier.versions[from] != from_version || simplifier.versions[to] != to_version {
+                continue;
+            }
+            // The heap is ordered by the border weighted cost, so a collapse
+            // over the limit doesn't mean every later one is too.
+            if simplifier.deviation(from, to) > max_cost {
+                continue;
+            }
+            alive_tris -= simplifier.collapse(from, to);
+        }
+
+        let alive = simplifier.alive;
+        let mut index = 0;
+        self.tris.retain(|_| {
+            index += 1;
+            alive[index - 1]
+        });
+        self.remove_unreferenced();
+        before - self.tris.len()
+    }
+}
+
+impl<'a> Simplifier<'a> {
+    fn new(verts: &'a [Vertex], tris: &'a mut [Triangle]) -> Self {
+        let mut alive = vec![true; tris.len()];
+        let mut vert_tris = vec![vec![]; verts.len()];
+        let mut quadrics = vec![Quadric::default(); verts.len()];
+        let mut face_quadrics = vec![Quadric::default(); verts.len()];
+        let mut face_normals = vec![Vector3::zeros(); tris.len()];
+        for (index, tri) in tris.iter().enumerate() {
+            let [a, b, c] = tri.verts.map(|vert| verts[vert]);
+            // Degenerate triangles have no plane and are gone after any collapse
+            // touching them anyway.
+            let Some(normal) = (b - a).cross(&(c - a)).try_normalize(f32::EPSILON) else {
+                alive[index] = false;
+                continue;
+            };
+            face_normals[index] = normal;
+            let quadric = Quadric::from_point_normal(&a, &normal);
+            for vert in tri.verts {
+                quadrics[vert] += quadric;
+                face_quadrics[vert] += quadric;
+                vert_tris[vert].push(index);
+            }
+        }
+
+        let mut simplifier = Simplifier {
+            verts,
+            tris,
+            alive,
+            vert_tris,
+            quadrics,
+            face_quadrics,
+            versions: vec![0; verts.len()],
+            heap: BinaryHeap::new(),
+        };
+
+        // Planes standing upright on every border edge make moving away from
+        // the border expensive, so seams and outlines keep their shape while
+        // their vertices slide along them.
+        for (tri, face_normal) in face_normals.iter().enumerate() {
+            if !simplifier.alive[tri] {
+                continue;
+            }
+            let tri_verts = simplifier.tris[tri].verts;
+            for corner in 0..3 {
+                let (a, b) = (tri_verts[corner], tri_verts[(corner + 1) % 3]);
+                if !simplifier.is_border_edge(a, b) {
+                    continue;
+                }
+                let edge = verts[b] - verts[a];
+                let Some(normal) = edge.cross(face_normal).try_normalize(f32::EPSILON) else {
+                    continue;
+                };
+                let quadric = Quadric::from_point_normal(&verts[a], &normal)
+                    .scaled(BORDER_WEIGHT * edge.norm_squared() as f64);
+                simplifier.quadrics[a] += quadric;
+                simplifier.quadrics[b] += quadric;
+            }
+        }
+
+        for vert in 0..verts.len() {
+            simplifier.queue_edges(vert);
+        }
+        simplifier
+    }
+
+    fn alive_tris(&self, vert: usize) -> impl Iterator<Item = usize> + '_ {
+        self.vert_tris[vert]
+            .iter()
+            .copied()
+            .filter(|tri| self.alive[*tri])
+    }
+
+    fn neighbours(&self, vert: usize) -> HashSet<usize> {
+        self.alive_tris(vert)
+            .flat_map(|tri| self.tris[tri].verts)
+            .filter(|other| *other != vert)
+            .collect()
+    }
+
+    fn shared_tris(&self, a: usize, b: usize) -> impl Iterator<Item = usize> + '_ {
+        self.alive_tris(a)
+            .filter(move |tri| self.tris[*tri].verts.contains(&b))
+    }
+
+    /// Where `vert` sits in the triangle, as a corner index.
+    fn corner(&self, tri: usize, vert: usize) -> usize {
+        self.tris[tri]
+            .verts
+            .iter()
+            .position(|other| *other == vert)
+            .unwrap()
+    }
+
+    /// What the triangle draws at `vert`: its texture coordinate, normal and
+    /// material. Triangles around a vertex that agree on this form one wedge.
+    fn wedge(&self, tri: usize, vert: usize) -> Wedge {
+        let corner = self.corner(tri, vert);
+        let triangle = &self.tris[tri];
+        (
+            triangle.texes[corner],
+            triangle.norms[corner],
+            Arc::as_ptr(&triangle.mtl),
+        )
+    }
+
+    /// Whether the edge is an open edge, non manifold, or separates two
+    /// wedges at either end (a UV seam, normal seam or material boundary).
+    fn is_border_edge(&self, a: usize, b: usize) -> bool {
+        match self.shared_tris(a, b).collect::<Vec<_>>()[..] {
+            [first, second] => {
+                self.wedge(first, a) != self.wedge(second, a)
+                    || self.wedge(first, b) != self.wedge(second, b)
+            }
+            _ => true,
+        }
+    }
+
+    /// Where `vert` may collapse to. Interior vertices can go to any neighbour.
+    /// Vertices in the middle of a border can only slide along it, and corners
+    /// where borders meet or the surface isn't manifold stay put.
+    fn destinations(&self, vert: usize) -> Vec<usize> {
+        let neighbours = self.neighbours(vert);
+        let mut border = vec![];
+        for other in &neighbours {
+            if self.shared_tris(vert, *other).count() > 2 {
+                return vec![];
+            }
+            if self.is_border_edge(vert, *other) {
+                border.push(*other);
+            }
+        }
+        match border.len() {
+            0 => neighbours.into_iter().collect(),
+            2 => border,
+            _ => vec![],
+        }
+    }
+
+    fn queue_edges(&mut self, vert: usize) {
+        let mut candidates: Vec<(usize, usize)> = self
+            .destinations(vert)
+            .into_iter()
+            .map(|to| (vert, to))
+            .collect();
+        for other in self.neighbours(vert) {
+            if self.destinations(other).contains(&vert) {
+                candidates.push((other, vert));
+            }
+        }
+        for (from, to) in candidates {
+            let mut quadric = self.quadrics[from];
+            quadric += self.quadrics[to];
+            let cost = quadric.error(&self.verts[to]);
+            self.heap.push((
+                Reverse(OrderedFloat(cost)),
+                from,
+                to,
+                self.versions[from],
+                self.versions[to],
+            ));
+        }
+    }
+
+    /// Sum of squared distances from `to` to the face planes both ends have
+    /// gathered. No single plane ends up further away than its square root.
+    fn deviation(&self, from: usize, to: usize) -> f64 {
+        let mut quadric = self.face_quadrics[from];
+        quadric += self.face_quadrics[to];
+        quadric.error(&self.verts[to])
+    }
+
+    /// Move `from` onto `to` if that keeps the mesh manifold, unflipped and the
+    /// seams intact. Returns how many triangles disappeared.
+    fn collapse(&mut self, from: usize, to: usize) -> usize {
+        if !self.destinations(from).contains(&to) {
+            return 0;
+        }
+        let shared: Vec<usize> = self.shared_tris(from, to).collect();
+        if shared.is_empty() {
+            return 0;
+        }
+
+        // Link condition: the only vertices both ends share are the tips of the
+        // triangles along the edge, otherwise the collapse pinches the surface.
+        let tips: HashSet<usize> = shared
+            .iter()
+            .flat_map(|tri| self.tris[*tri].verts)
+            .filter(|vert| *vert != from && *vert != to)
+            .collect();
+        let to_neighbours = self.neighbours(to);
+        if self
+            .neighbours(from)
+            .into_iter()
+            .any(|vert| vert != to && to_neighbours.contains(&vert) && !tips.contains(&vert))
+        {
+            return 0;
+        }
+
+        // Every triangle that moves takes the attributes `to` has in the
+        // triangle along the edge from the same wedge of `from`. When there's
+        // no such triangle, or they disagree, there's nothing sensible to give it.
+        let moved: Vec<usize> = self
+            .alive_tris(from)
+            .filter(|tri| !shared.contains(tri))
+            .collect();
+        // With nothing left to move the collapse would just delete a piece of
+        // the mesh, like the last triangle of a flat patch.
+        if moved.is_empty() {
+            return 0;
+        }
+        let mut attributes = Vec::with_capacity(moved.len());
+        for tri in &moved {
+            let wedge = self.wedge(*tri, from);
+            let mut sources = shared
+                .iter()
+                .filter(|shared| self.wedge(**shared, from) == wedge)
+                .map(|shared| self.wedge(*shared, to));
+            let Some(source) = sources.next() else {
+                return 0;
+            };
+            if sources.any(|other| other != source) {
+                return 0;
+            }
+            attributes.push(source);
+        }
+
+        let mut kept: HashSet<[usize; 3]> = self
+            .alive_tris(to)
+            .filter(|tri| !shared.contains(tri))
+            .map(|tri| sorted(self.tris[tri].verts))
+            .collect();
+        for tri in &moved {
+            let [a, b, c] = self.tris[*tri].verts.map(|vert| self.verts[vert]);
+            let before = (b - a).cross(&(c - a));
+            let moved_verts = self.tris[*tri]
+                .verts
+                .map(|vert| if vert == from { to } else { vert });
+            let [a, b, c] = moved_verts.map(|vert| self.verts[vert]);
+            let after = (b - a).cross(&(c - a));
+            match (
+                before.try_normalize(f32::EPSILON),
+                after.try_normalize(f32::EPSILON),
+            ) {
+                (Some(before), Some(after)) if before.dot(&after) >= MIN_NORMAL_COS => {}
+                _ => return 0,
+            }
+            // Folding onto a triangle that's already there, like the last
+            // collapse of a tetrahedron, would leave a two sided sliver.
+            if !kept.insert(sorted(moved_verts)) {
+                return 0;
+            }
+        }
+
+        for tri in &shared {
+            self.alive[*tri] = false;
+        }
+        for (tri, (tex, norm, _)) in moved.iter().zip(attributes) {
+            let corner = self.corner(*tri, from);
+            let triangle = &mut self.tris[*tri];
+            triangle.verts[corner] = to;
+            triangle.texes[corner] = tex;
+            triangle.norms[corner] = norm;
+        }
+        let from_tris = std::mem::take(&mut self.vert_tris[from]);
+        self.vert_tris[to].extend(from_tris.into_iter().filter(|tri| self.alive[*tri]));
+        self.vert_tris[to].retain(|tri| self.alive[*tri]);
+        let quadric = self.quadrics[from];
+        self.quadrics[to] += quadric;
+        let quadric = self.face_quadrics[from];
+        self.face_quadrics[to] += quadric;
+
+        // Everything around `to` may have changed cost or freedom.
+        let around = self.neighbours(to);
+        self.versions[from] += 1;
+        self.versions[to] += 1;
+        for vert in &around {
+            self.versions[*vert] += 1;
+        }
+        self.queue_edges(to);
+        for vert in around {
+            self.queue_edges(vert);
+        }
+        shared.len()
+    }
+}
+
+fn sorted(mut verts: [usize; 3]) -> [usize; 3] {
+    verts.sort_unstable();
+    verts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Point3;
+
+    /// A 5x5 grid folded into a V along x = 2, with per-vertex normals.
+    fn valley() -> GenericMesh {
+        let mut verts = vec![];
+        for y in 0..5 {
+            for x in 0..5 {
+                verts.push(Point3::new(x as f32, y as f32, (x as f32 - 2.0).abs()));
+            }
+        }
+        let mtl = Arc::new(Material::default());
+        let mut tris = vec![];
+        for y in 0..4 {
+            for x in 0..4 {
+                let corner = y * 5 + x;
+                for verts in [
+                    [corner, corner + 1, corner + 6],
+                    [corner, corner + 6, corner + 5],
+                ] {
+                    tris.push(Triangle {
+                        verts,
+                        texes: [0; 3],
+                        norms: [0; 3],
+                        mtl: mtl.clone(),
+                    });
+                }
+            }
+        }
+        let normals = vec![Vector3::z(); verts.len()];
+        GenericMesh {
+            verts,
+            tris,
+            texture_coords: vec![],
+            normals,
+            colors: vec![],
+        }
+    }
+
+    #[test]
+    fn error_bound_keeps_the_fold() {
+        let mut mesh = valley();
+        let removed = mesh.simplify(0, 1e-3);
+        assert!(removed > 0);
+        // A triangle bridging the fold would sag above it in the middle.
+        for tri in &mesh.tris {
+            let center = tri
+                .verts
+                .iter()
+                .map(|vert| mesh.verts[*vert].coords)
+                .sum::<Vector3<f32>>()
+                / 3.0;
+            assert!((center.z - (center.x - 2.0).abs()).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn unbounded_simplify_goes_further() {
+        let mut bounded = valley();
+        let mut unbounded = valley();
+        assert!(unbounded.simplify(0, f32::INFINITY) > bounded.simplify(0, 1e-3));
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::sync::Arc;
+
+use nalgebra::{Point3, Vector3};
+
+use crate::mesh::loader::{GenericMesh, vertex_normals};
+use crate::mesh::{Material, Normal, Triangle, Vertex};
+
+const HEADER_BYTES: usize = 80;
+const FACET_BYTES: usize = 50;
+
+/// One facet as stored in the file, before vertices are welded.
+struct Facet {
+    normal: Normal,
+    verts: [Vertex; 3],
+}
+
+impl GenericMesh {
+    /// Load an ASCII or binary STL. Identical vertices are welded together. With
+    /// `smooth_normals` normals are averaged per vertex, otherwise every facet
+    /// keeps its own flat normal (recomputed when the file stores a zero one).
+    pub fn from_stl(file_name: &str, smooth_normals: bool) -> Result<Self, Box<dyn Error>> {
+        let bytes = fs::read(file_name).map_err(|e| format!("Couldn't open file: {file_name}"))?;
+        let facets = if is_binary_stl(&bytes) {
+            parse_binary(&bytes)
+        } else if bytes.trim_ascii_start().starts_with(b"solid") {
+            parse_ascii(std::str::from_utf8(&bytes)?)?
+        } else {
+            return Err(format!("Not an ASCII or binary STL: {file_name}").into());
+        };
+
+        let material: Arc<Material> = Arc::new(Default::default());
+        let mut verts: Vec<Vertex> = vec![];
+        let mut welded: HashMap<[u32; 3], usize> = HashMap::new();
+        let mut facet_normals: Vec<Normal> = vec![];
+        let mut tris: Vec<Triangle> = Vec::with_capacity(facets.len());
+
+        for facet in facets {
+            let indices = facet.verts.map(|vert| {
+                // Bitwise equality is what CAD exporters give us for shared corners.
+                let key = [vert.x.to_bits(), vert.y.to_bits(), vert.z.to_bits()];
+                *welded.entry(key).or_insert_with(|| {
+                    verts.push(vert);
+                    verts.len() - 1
+                })
+            });
+
+            let mut normal = facet.normal;
+            if normal.norm_squared() == 0.0 || !normal.iter().all(|c| c.is_finite()) {
+                normal =
+                    (facet.verts[1] - facet.verts[0]).cross(&(facet.verts[2] - facet.verts[0]));
+            }
+            // Turned around to point into the surface like `vertex_normals` does
+            // for the swapped winding below, which is what the lighting expects.
+            facet_normals.push(-normal.try_normalize(0.0).unwrap_or_else(Vector3::zeros));
+
+            // Normal indices are one based, 0 makes the renderer use per-vertex normals.
+            let norm = if smooth_normals {
+                0
+            } else {
+                facet_normals.len()
+            };
+            // Swap the first two corners like `clip_ears` does for OBJ faces, the
+            // rasterizer's front face test expects that winding.
+            tris.push(Triangle {
+                verts: [indices[1], indices[0], indices[2]],
+                texes: [0; 3],
+                norms: [norm; 3],
+                mtl: material.clone(),
+            });
+        }
+
+        let normals = if smooth_normals {
+            vertex_normals(&verts, &tris)
+        } else {
+            facet_normals
+        };
+
+        Ok(GenericMesh {
+            verts,
+            tris,
+            texture_coords: vec![],
+            normals,
+            colors: vec![],
+        })
+    }
+}
+
+/// A binary STL is exactly a header, a facet count and that many facets. Plenty
+/// of binary files still start with `solid`, so the size is what decides.
+fn is_binary_stl(bytes: &[u8]) -> bool {
+    if bytes.len() < HEADER_BYTES + 4 {
+        return false;
+    }
+    let count = u32::from_le_bytes(bytes[HEADER_BYTES..HEADER_BYTES + 4].try_into().unwrap());
+    (count as usize)
+        .checked_mul(FACET_BYTES)
+        .and_then(|facet_bytes| facet_bytes.checked_add(HEADER_BYTES + 4))
+        == Some(bytes.len())
+}
+
+fn parse_binary(bytes: &[u8]) -> Vec<Facet> {
+    let read_vec = |chunk: &[u8], offset: usize| -> [f32; 3] {
+        [0, 1, 2].map(|i| {
+            let start = offset + i * 4;
+            f32::from_le_bytes(chunk[start..start + 4].try_into().unwrap())
+        })
+    };
+
+    bytes[HEADER_BYTES + 4..]
+        .chunks_exact(FACET_BYTES)
+        .map(|chunk| {
+            let [nx, ny, nz] = read_vec(chunk, 0);
+            Facet {
+                normal: Vector3::new(nx, ny, nz),
+                verts: [12, 24, 36].map(|offset| {
+                    let [x, y, z] = read_vec(chunk, offset);
+                    Point3::new(x, y, z)
+                }),
+            }
+        })
+        .collect()
+}
+
+fn parse_ascii(contents: &str) -> Result<Vec<Facet>, Box<dyn Error>> {
+    let mut facets: Vec<Facet> = vec![];
+    let mut normal: Normal = Vector3::zeros();
+    let mut facet_verts: Vec<Vertex> = vec![];
+
+    for (lineno, line) in contents.lines().enumerate() {
+        let mut components = line.split_whitespace();
+        match components.next() {
+            Some("facet") => {
+                if components.next() != Some("normal") {
+                    return Err(format!("Expected facet normal at line: {}", lineno + 1).into());
+                }
+                normal = Vector3::from(parse_triple(&mut components, "normal", lineno)?);
+                facet_verts.clear();
+            }
+            Some("vertex") => {
+                facet_verts.push(Point3::from(parse_triple(
+                    &mut components,
+                    "vertex",
+                    lineno,
+                )?));
+            }
+            Some("endfacet") => {
+                let verts: [Vertex; 3] = facet_verts.as_slice().try_into().map_err(|e| {
+                    format!(
+                        "Facet ending at line: {} has {} vertices, expected 3",
+                        lineno + 1,
+                        facet_verts.len()
+                    )
+                })?;
+                facets.push(Facet { normal, verts });
+            }
+            _ => continue,
+        }
+    }
+    Ok(facets)
+}
+
+fn parse_triple<'a>(
+    components: &mut impl Iterator<Item = &'a str>,
+    what: &str,
+    lineno: usize,
+) -> Result<[f32; 3], String> {
+    let mut triple = [0.0; 3];
+    for component in &mut triple {
+        *component = components
+            .next()
+            .ok_or(format!("Missing {what} component at line: {}", lineno + 1))?
+            .parse()
+            .map_err(|e| format!("Invalid digit for {what} at line: {}", lineno + 1))?;
+    }
+    Ok(triple)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLE: &str = "solid t\nfacet normal 0 0 1\nouter loop\n\
+        vertex 0 0 0\nvertex 1 0 0\nvertex 0 1 0\nendloop\nendfacet\nendsolid t\n";
+
+    fn load(
+        name: &str,
+        contents: &[u8],
+        smooth_normals: bool,
+    ) -> Result<GenericMesh, Box<dyn Error>> {
+        let path =
+            std::env::temp_dir().join(format!("engine-stl-{name}-{}.stl", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        let mesh = GenericMesh::from_stl(path.to_str().unwrap(), smooth_normals);
+        fs::remove_file(&path).unwrap();
+        mesh
+    }
+
+    #[test]
+    fn flat_and_smooth_normals_agree() {
+        let flat = load("flat", TRIANGLE.as_bytes(), false).unwrap();
+        let smooth = load("smooth", TRIANGLE.as_bytes(), true).unwrap();
+        assert_eq!(flat.normals, vec![Vector3::new(0.0, 0.0, -1.0)]);
+        for normal in &smooth.normals {
+            assert!((normal - flat.normals[0]).norm() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn facet_with_two_vertices_is_rejected() {
+        let broken = TRIANGLE.replace("vertex 0 1 0\n", "");
+        assert!(load("short-facet", broken.as_bytes(), false).is_err());
+    }
+
+    #[test]
+    fn truncated_binary_is_rejected() {
+        let mut bytes = vec![0u8; HEADER_BYTES];
+        bytes.extend(2u32.to_le_bytes());
+        bytes.extend([0u8; FACET_BYTES]);
+        assert!(load("truncated", &bytes, false).is_err());
+    }
+}
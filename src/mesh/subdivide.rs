@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use nalgebra::Point3;
+
+use crate::mesh::loader::{GenericMesh, vertex_normals};
+use crate::mesh::{Color, Material, TextureCoord, Triangle, Vertex};
+
+/// Which refinement rule [`GenericMesh::subdivide`] uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Subdivision {
+    /// Splits every triangle into four, made for triangle meshes.
+    Loop,
+    /// Splits every face into quads, one per corner. Meshes made of quads that
+    /// were triangulated on import come out a little less even than with Loop.
+    CatmullClark,
+}
+
+/// A polygon while subdividing. Catmull-Clark produces quads, which only get
+/// triangulated once all levels are done.
+struct Face {
+    verts: Vec<usize>,
+    /// Texture coordinate per corner, `None` when the face isn't textured.
+    uvs: Option<Vec<TextureCoord>>,
+    mtl: Arc<Material>,
+}
+
+/// Vertex data for one level. `colors` is empty or indexed like `verts`.
+struct Level {
+    verts: Vec<Vertex>,
+    colors: Vec<Color>,
+    faces: Vec<Face>,
+}
+
+/// How a vertex is refined, based on the edges around it.
+enum VertexKind {
+    Interior,
+    /// On an open edge, with its two neighbours along it.
+    Boundary(usize, usize),
+    /// Where the surface isn't manifold or an open edge dead ends. Stays put.
+    Corner,
+}
+
+impl GenericMesh {
+    /// Refine the mesh `levels` times. Texture coordinates are interpolated
+    /// linearly across each face, so seams stay where they are, and open edges
+    /// are smoothed as curves of their own. Normals are recomputed afterwards,
+    /// which drops any hard edges the mesh had. Triangles using a vertex more
+    /// than once have no area to refine and are dropped.
+    pub fn subdivide(&mut self, scheme: Subdivision, levels: usize) {
+        if levels == 0 {
+            return;
+        }
+        let colors = if self.colors.len() == self.verts.len() {
+            std::mem::take(&mut self.colors)
+        } else {
+            vec![]
+        };
+        let texture_coords = &self.texture_coords;
+        let mut level = Level {
+            verts: std::mem::take(&mut self.verts),
+            colors,
+            // A repeated vertex would leave an edge without a vertex opposite it.
+            faces: self
+                .tris
+                .iter()
+                .filter(|tri| {
+                    let [a, b, c] = tri.verts;
+                    a != b && b != c && a != c
+                })
+                .map(|tri| Face {
+                    verts: tri.verts.to_vec(),
+                    uvs: tri
+                        .texes
+                        .iter()
+                        .map(|tex| texture_coords.get(*tex).copied())
+                        .collect(),
+                    mtl: tri.mtl.clone(),
+                })
+                .collect(),
+        };
+        for _ in 0..levels {
+            level = match scheme {
+                Subdivision::Loop => level.loop_step(),
+                Subdivision::CatmullClark => level.catmull_clark_step(),
+            };
+        }
+
+        let mut texture_coords: Vec<TextureCoord> = vec![];
+        let mut uv_indices: HashMap<[u32; 3], usize> = HashMap::new();
+        let mut tris: Vec<Triangle> = vec![];
+        for face in &level.faces {
+            let texes: Vec<usize> = match &face.uvs {
+                Some(uvs) => uvs
+                    .iter()
+                    .map(|uv| {
+                        let key = [uv.u.to_bits(), uv.v.to_bits(), uv.w.to_bits()];
+                        *uv_indices.entry(key).or_insert_with(|| {
+                            texture_coords.push(*uv);
+                            texture_coords.len() - 1
+                        })
+                    })
+                    .collect(),
+                None => vec![0; face.verts.len()],
+            };
+            for i in 1..face.verts.len() - 1 {
+                tris.push(Triangle {
+                    verts: [face.verts[0], face.verts[i], face.verts[i + 1]],
+                    texes: [texes[0], texes[i], texes[i + 1]],
+                    norms: [0; 3],
+                    mtl: face.mtl.clone(),
+                });
+            }
+        }
+
+        self.normals = vertex_normals(&level.verts, &tris);
+        self.verts = level.verts;
+        self.colors = level.colors;
+        self.texture_coords = texture_coords;
+        self.tris = tris;
+    }
+}
+
+impl Level {
+    /// Faces on every edge, keyed by its endpoints in ascending order.
+    fn edge_faces(&self) -> HashMap<[usize; 2], Vec<usize>> {
+        let mut edges: HashMap<[usize; 2], Vec<usize>> = HashMap::new();
+        for (index, face) in self.faces.iter().enumerate() {
+            for (a, b) in corners(face) {
+                edges
+                    .entry(edge_key(face.verts[a], face.verts[b]))
+                    .or_default()
+                    .push(index);
+            }
+        }
+        edges
+    }
+
+    fn vertex_kinds(&self, edges: &HashMap<[usize; 2], Vec<usize>>) -> Vec<VertexKind> {
+        let mut open: Vec<Vec<usize>> = vec![vec![]; self.verts.len()];
+        // Vertices no face uses count as non manifold too, they just stay put.
+        let mut non_manifold = vec![true; self.verts.len()];
+        for [a, b] in edges.keys() {
+            non_manifold[*a] = false;
+            non_manifold[*b] = false;
+        }
+        for ([a, b], faces) in edges {
+            match faces.len() {
+                1 => {
+                    open[*a].push(*b);
+                    open[*b].push(*a);
+                }
+                2 => {}
+                _ => {
+                    non_manifold[*a] = true;
+                    non_manifold[*b] = true;
+                }
+            }
+        }
+        open.iter()
+            .zip(non_manifold)
+            .map(|(open, non_manifold)| match open[..] {
+                _ if non_manifold => VertexKind::Corner,
+                [] => VertexKind::Interior,
+                [a, b] => VertexKind::Boundary(a, b),
+                _ => VertexKind::Corner,
+            })
+            .collect()
+    }
+
+    /// Each vertex's neighbours along edges.
+    fn neighbours(&self, edges: &HashMap<[usize; 2], Vec<usize>>) -> Vec<Vec<usize>> {
+        let mut neighbours: Vec<Vec<usize>> = vec![vec![]; self.verts.len()];
+        for [a, b] in edges.keys() {
+            neighbours[*a].push(*b);
+            neighbours[*b].push(*a);
+        }
+        neighbours
+    }
+
+    /// Blend of existing vertices, applied to positions and colors alike.
+    fn mix(&self, weights: &[(usize, f32)]) -> (Vertex, Option<Color>) {
+        let position = weights
+            .iter()
+            .fold(Point3::origin(), |sum, (vert, weight)| {
+                sum + self.verts[*vert].coords * *weight
+            });
+        let color = (!self.colors.is_empty()).then(|| {
+            weights.iter().fold(
+                Color {
+                    r: 0.0,
+                    g: 0.0,
+                    b: 0.0,
+                    a: self.colors[weights[0].0].a,
+                },
+                |sum, (vert, weight)| sum + self.colors[*vert] * *weight,
+            )
+        });
+        (position, color)
+    }
+
+    fn push_mixed(&self, next: &mut Level, weights: &[(usize, f32)]) -> usize {
+        let (position, color) = self.mix(weights);
+        next.verts.push(position);
+        next.colors.extend(color);
+        next.verts.len() - 1
+    }
+
+    /// Old vertex positions under the boundary and corner rules shared by both
+    /// schemes, with `interior` filling in the rest.
+    fn smoothed_verts(
+        &self,
+        kinds: &[VertexKind],
+        next: &mut Level,
+        interior: impl Fn(usize) -> Vec<(usize, f32)>,
+    ) {
+        for (vert, kind) in kinds.iter().enumerate() {
+            let weights = match kind {
+                VertexKind::Interior => interior(vert),
+                VertexKind::Boundary(a, b) => vec![(vert, 0.75), (*a, 0.125), (*b, 0.125)],
+                VertexKind::Corner => vec![(vert, 1.0)],
+            };
+            self.push_mixed(next, &weights);
+        }
+    }
+
+    fn loop_step(self) -> Level {
+        let edges = self.edge_faces();
+        let kinds = self.vertex_kinds(&edges);
+        let neighbours = self.neighbours(&edges);
+        let mut next = Level {
+            verts: Vec::with_capacity(self.verts.len() + edges.len()),
+            colors: vec![],
+            faces: Vec::with_capacity(self.faces.len() * 4),
+        };
+
+        self.smoothed_verts(&kinds, &mut next, |vert| {
+            let valence = neighbours[vert].len();
+            let beta = if valence == 3 {
+                3.0 / 16.0
+            } else {
+                3.0 / (8.0 * valence as f32)
+            };
+            let mut weights = vec![(vert, 1.0 - valence as f32 * beta)];
+            weights.extend(neighbours[vert].iter().map(|other| (*other, beta)));
+            weights
+        });
+
+        let mut edge_points: HashMap<[usize; 2], usize> = HashMap::with_capacity(edges.len());
+        for ([a, b], faces) in &edges {
+            let weights = match faces[..] {
+                [first, second] => {
+                    let opposite = |face: usize| {
+                        *self.faces[face]
+                            .verts
+                            .iter()
+                            .find(|vert| *vert != a && *vert != b)
+                            .unwrap()
+                    };
+                    vec![
+                        (*a, 0.375),
+                        (*b, 0.375),
+                        (opposite(first), 0.125),
+                        (opposite(second), 0.125),
+                    ]
+                }
+                _ => vec![(*a, 0.5), (*b, 0.5)],
+            };
+            edge_points.insert([*a, *b], self.push_mixed(&mut next, &weights));
+        }
+
+        for face in &self.faces {
+            let [a, b, c] = [0, 1, 2].map(|corner| face.verts[corner]);
+            let [ab, bc, ca] = [(a, b), (b, c), (c, a)].map(|(x, y)| edge_points[&edge_key(x, y)]);
+            let uvs = face.uvs.as_ref().map(|uvs| {
+                let [ab, bc, ca] =
+                    [(0, 1), (1, 2), (2, 0)].map(|(x, y)| average(&[uvs[x], uvs[y]]));
+                [uvs[0], uvs[1], uvs[2], ab, bc, ca]
+            });
+            // Same corner order as the parent, so the winding is kept.
+            for corners in [[0, 3, 5], [3, 1, 4], [5, 4, 2], [3, 4, 5]] {
+                let verts = [a, b, c, ab, bc, ca];
+                next.faces.push(Face {
+                    verts: corners.iter().map(|corner| verts[*corner]).collect(),
+                    uvs: uvs.map(|uvs| corners.iter().map(|corner| uvs[*corner]).collect()),
+                    mtl: face.mtl.clone(),
+                });
+            }
+        }
+        next
+    }
+
+    fn catmull_clark_step(self) -> Level {
+        let edges = self.edge_faces();
+        let kinds = self.vertex_kinds(&edges);
+        let neighbours = self.neighbours(&edges);
+        let mut vert_faces: Vec<Vec<usize>> = vec![vec![]; self.verts.len()];
+        for (index, face) in self.faces.iter().enumerate() {
+            for vert in &face.verts {
+                vert_faces[*vert].push(index);
+            }
+        }
+        let face_average = |face: usize, scale: f32| -> Vec<(usize, f32)> {
+            let verts = &self.faces[face].verts;
+            let weight = scale / verts.len() as f32;
+            verts.iter().map(|vert| (*vert, weight)).collect()
+        };
+        let mut next = Level {
+            verts: Vec::with_capacity(self.verts.len() + self.faces.len() + edges.len()),
+            colors: vec![],
+            faces: Vec::with_capacity(self.faces.len() * 4),
+        };
+
+        // (F + 2R + (n - 3)P) / n, with F the average of the surrounding face
+        // points and R the average of the surrounding edge midpoints.
+        self.smoothed_verts(&kinds, &mut next, |vert| {
+            let valence = neighbours[vert].len() as f32;
+            let faces = &vert_faces[vert];
+            let mut weights = vec![(vert, (valence - 3.0) / valence)];
+            for face in faces {
+                weights.extend(face_average(*face, 1.0 / (valence * faces.len() as f32)));
+            }
+            for other in &neighbours[vert] {
+                weights.push((vert, 1.0 / (valence * valence)));
+                weights.push((*other, 1.0 / (valence * valence)));
+            }
+            weights
+        });
+
+        let face_points: Vec<usize> = (0..self.faces.len())
+            .map(|face| self.push_mixed(&mut next, &face_average(face, 1.0)))
+            .collect();
+
+        let mut edge_points: HashMap<[usize; 2], usize> = HashMap::with_capacity(edges.len());
+        for ([a, b], faces) in &edges {
+            let weights = match faces[..] {
+                [first, second] => {
+                    let mut weights = vec![(*a, 0.25), (*b, 0.25)];
+                    weights.extend(face_average(first, 0.25));
+                    weights.extend(face_average(second, 0.25));
+                    weights
+                }
+                _ => vec![(*a, 0.5), (*b, 0.5)],
+            };
+            edge_points.insert([*a, *b], self.push_mixed(&mut next, &weights));
+        }
+
+        for (index, face) in self.faces.iter().enumerate() {
+            let count = face.verts.len();
+            let center_uv = face.uvs.as_ref().map(|uvs| average(uvs));
+            for corner in 0..count {
+                let (prev, next_corner) = ((corner + count - 1) % count, (corner + 1) % count);
+                let vert = face.verts[corner];
+                let to_next = edge_points[&edge_key(vert, face.verts[next_corner])];
+                let from_prev = edge_points[&edge_key(face.verts[prev], vert)];
+                // Corner, edge towards the next corner, center, edge from the
+                // previous one: the parent's winding.
+                next.faces.push(Face {
+                    verts: vec![vert, to_next, face_points[index], from_prev],
+                    uvs: face.uvs.as_ref().zip(center_uv).map(|(uvs, center)| {
+                        vec![
+                            uvs[corner],
+                            average(&[uvs[corner], uvs[next_corner]]),
+                            center,
+                            average(&[uvs[prev], uvs[corner]]),
+                        ]
+                    }),
+                    mtl: face.mtl.clone(),
+                });
+            }
+        }
+        next
+    }
+}
+
+/// Consecutive corner pairs around a face, wrapping around at the end.
+fn corners(face: &Face) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let count = face.verts.len();
+    (0..count).map(move |corner| (corner, (corner + 1) % count))
+}
+
+fn edge_key(a: usize, b: usize) -> [usize; 2] {
+    [a.min(b), a.max(b)]
+}
+
+fn average(uvs: &[TextureCoord]) -> TextureCoord {
+    let scale = 1.0 / uvs.len() as f32;
+    let sum = uvs.iter().fold((0.0, 0.0, 0.0), |(u, v, w), uv| {
+        (u + uv.u, v + uv.v, w + uv.w)
+    });
+    TextureCoord {
+        u: sum.0 * scale,
+        v: sum.1 * scale,
+        w: sum.2 * scale,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A triangle next to a degenerate one, like `f 1 2 3` and `f 1 4 1`.
+    fn with_degenerate_face() -> GenericMesh {
+        let mtl: Arc<Material> = Arc::new(Default::default());
+        let tri = |verts| Triangle {
+            verts,
+            texes: [0; 3],
+            norms: [0; 3],
+            mtl: mtl.clone(),
+        };
+        GenericMesh {
+            verts: vec![
+                Vertex::new(0.0, 0.0, 0.0),
+                Vertex::new(1.0, 0.0, 0.0),
+                Vertex::new(0.0, 1.0, 0.0),
+                Vertex::new(1.0, 1.0, 0.0),
+            ],
+            tris: vec![tri([1, 0, 2]), tri([3, 0, 0])],
+            texture_coords: vec![],
+            normals: vec![],
+            colors: vec![],
+        }
+    }
+
+    #[test]
+    fn loop_skips_degenerate_faces() {
+        let mut mesh = with_degenerate_face();
+        mesh.subdivide(Subdivision::Loop, 1);
+        assert_eq!(mesh.tris.len(), 4);
+    }
+
+    #[test]
+    fn catmull_clark_skips_degenerate_faces() {
+        let mut mesh = with_degenerate_face();
+        mesh.subdivide(Subdivision::CatmullClark, 1);
+        // Three quads, each drawn as two triangles.
+        assert_eq!(mesh.tris.len(), 6);
+    }
+}
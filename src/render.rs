@@ -0,0 +1,265 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use nalgebra::{Point2, Point3};
+use rayon::prelude::*;
+
+use crate::mesh::Color;
+
+/// Color of triangle edges in the line drawing render modes.
+const EDGE_COLOR: Color = Color {
+    r: 0.0,
+    g: 0.0,
+    b: 0.0,
+    a: 1.0,
+};
+/// How far an edge can be behind the depth buffer and still show, as a
+/// fraction of its distance from the camera. Keeps edges from flickering in
+/// and out of the triangles they belong to.
+const EDGE_DEPTH_BIAS: f32 = 0.005;
+
+/// What `World::draw` puts on screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RenderMode {
+    /// Lit and textured triangles.
+    Shaded,
+    /// Every triangle edge, including those facing away or hidden behind
+    /// others.
+    Wireframe,
+    /// Shaded triangles with their visible edges drawn over them.
+    Overlay,
+    /// Only the edges that aren't hidden behind other triangles.
+    HiddenLine,
+}
+
+impl RenderMode {
+    /// The mode after this one, wrapping around.
+    pub fn next(self) -> Self {
+        match self {
+            RenderMode::Shaded => RenderMode::Wireframe,
+            RenderMode::Wireframe => RenderMode::Overlay,
+            RenderMode::Overlay => RenderMode::HiddenLine,
+            RenderMode::HiddenLine => RenderMode::Shaded,
+        }
+    }
+}
+
+/// Overdraw at or above this many depth test passes shows as the hottest color.
+const OVERDRAW_MAX: u32 = 8;
+/// Shown in the UV debug view where a triangle has no texture coordinates.
+pub const MISSING_UV_COLOR: Color = Color {
+    r: 1.0,
+    g: 0.0,
+    b: 1.0,
+    a: 1.0,
+};
+
+/// Something to show in place of the lit color of every pixel, for finding
+/// out what's wrong with a model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DebugView {
+    /// Distance from the camera, white nearest and black furthest across the
+    /// scene.
+    Depth,
+    /// View space normals, x, y and z as red, green and blue, so anything
+    /// facing the camera is blue unless its normals are flipped.
+    Normals,
+    /// Texture coordinates, u as red and v as green, repeating every 1.
+    Uvs,
+    /// A color per material picked from its name.
+    Materials,
+    /// How many times each pixel passed the depth test, from blue for once to
+    /// red for `OVERDRAW_MAX` or more.
+    Overdraw,
+}
+
+impl DebugView {
+    /// The view after `view`, going through no view at all after the last.
+    pub fn cycle(view: Option<Self>) -> Option<Self> {
+        match view {
+            None => Some(DebugView::Depth),
+            Some(DebugView::Depth) => Some(DebugView::Normals),
+            Some(DebugView::Normals) => Some(DebugView::Uvs),
+            Some(DebugView::Uvs) => Some(DebugView::Materials),
+            Some(DebugView::Materials) => Some(DebugView::Overdraw),
+            Some(DebugView::Overdraw) => None,
+        }
+    }
+}
+
+/// Per frame state the debug views need while drawing triangles.
+pub struct DebugFrame {
+    /// View space distances mapped to white and black by the depth view.
+    pub depth_range: [f32; 2],
+    /// Depth test passes per pixel, only filled in for the overdraw view.
+    pub overdraw: Vec<AtomicU32>,
+}
+
+impl DebugFrame {
+    /// Color every pixel by how many depth tests it passed, leaving those no
+    /// triangle covered alone.
+    pub fn paint_overdraw(&self, frame: &mut [u8]) {
+        frame
+            .par_chunks_exact_mut(4)
+            .zip(&self.overdraw)
+            .for_each(|(pixel, passes)| {
+                let passes = passes.load(Ordering::Relaxed);
+                if passes > 0 {
+                    let color = heat_color(passes);
+                    pixel.copy_from_slice(&[
+                        (color.r * 255.0) as u8,
+                        (color.g * 255.0) as u8,
+                        (color.b * 255.0) as u8,
+                        255,
+                    ]);
+                }
+            });
+    }
+}
+
+/// Xiaolin Wu's antialiased line between two screen space points, with
+/// depth interpolated along it. With `depth_test`, pixels further away
+/// than `zbuffer` are left alone.
+pub fn draw_line(
+    mut start: Point3<f32>,
+    mut end: Point3<f32>,
+    frame: &mut [u8],
+    zbuffer: &[AtomicU32],
+    (width, height): (usize, usize),
+    depth_test: bool,
+) {
+    // Step along whichever axis the line covers more of.
+    let steep = (end.y - start.y).abs() > (end.x - start.x).abs();
+    if steep {
+        start = Point3::new(start.y, start.x, start.z);
+        end = Point3::new(end.y, end.x, end.z);
+    }
+    if start.x > end.x {
+        std::mem::swap(&mut start, &mut end);
+    }
+    let run = end.x - start.x;
+    let (gradient, depth_gradient) = if run > 0.0 {
+        ((end.y - start.y) / run, (end.z - start.z) / run)
+    } else {
+        (0.0, 0.0)
+    };
+    let (major_len, minor_len) = if steep {
+        (height, width)
+    } else {
+        (width, height)
+    };
+
+    let mut plot = |major: usize, minor: f32, z: f32, coverage: f32| {
+        if minor < 0.0 || minor >= minor_len as f32 || coverage <= 0.0 {
+            return;
+        }
+        let (x, y) = if steep {
+            (minor as usize, major)
+        } else {
+            (major, minor as usize)
+        };
+        let index = y * width + x;
+        if depth_test {
+            let depth = f32::from_bits(zbuffer[index].load(Ordering::Relaxed));
+            if z - depth > EDGE_DEPTH_BIAS * (1.0 - depth) {
+                return;
+            }
+        }
+        let pixel = &mut frame[index * 4..index * 4 + 4];
+        for (channel, edge) in pixel
+            .iter_mut()
+            .zip([EDGE_COLOR.r, EDGE_COLOR.g, EDGE_COLOR.b])
+        {
+            *channel = (*channel as f32 * (1.0 - coverage) + edge * 255.0 * coverage) as u8;
+        }
+    };
+
+    // Only the part of the line that's on screen.
+    let first = start.x.round().max(0.0);
+    let last = end.x.round().min(major_len as f32 - 1.0);
+    if first > last {
+        return;
+    }
+    for major in first as usize..=last as usize {
+        let along = major as f32 - start.x;
+        let minor = start.y + gradient * along;
+        let z = start.z + depth_gradient * along;
+        let below = minor.floor();
+        let coverage = minor - below;
+        plot(major, below, z, 1.0 - coverage);
+        plot(major, below + 1.0, z, coverage);
+    }
+}
+
+/// Rasterize a triangle into `zbuffer` alone, without shading anything.
+pub fn fill_depth(
+    screen_verts: [Point2<f32>; 3],
+    z_values: [f32; 3],
+    zbuffer: &[AtomicU32],
+    (width, height): (usize, usize),
+) {
+    let [(x1, y1), (x2, y2), (x3, y3)] = screen_verts.map(|vert| (vert.x, vert.y));
+    let min_x = (x1.min(x2).min(x3).max(0.0)) as usize;
+    let max_x = (x1.max(x2).max(x3).min(width as f32 - 1.0) + 1.0) as usize;
+    let min_y = (y1.min(y2).min(y3).max(0.0)) as usize;
+    let max_y = (y1.max(y2).max(y3).min(height as f32 - 1.0) + 1.0) as usize;
+    if min_x > max_x || min_y > max_y {
+        return;
+    }
+
+    let edge = |(ax, ay): (f32, f32), (bx, by): (f32, f32), (px, py): (f32, f32)| -> f32 {
+        (py - ay) * (bx - ax) - (px - ax) * (by - ay)
+    };
+    zbuffer
+        .par_chunks_exact(width)
+        .skip(min_y)
+        .take(max_y - min_y)
+        .enumerate()
+        .for_each(|(row_idx, row)| {
+            let y = row_idx + min_y;
+            for (x, depth) in row.iter().enumerate().take(max_x + 1).skip(min_x) {
+                let p = (x as f32, y as f32);
+                let w1 = edge((x2, y2), (x3, y3), p);
+                let w2 = edge((x3, y3), (x1, y1), p);
+                let w3 = edge((x1, y1), (x2, y2), p);
+                if w1 < 0.0 || w2 < 0.0 || w3 < 0.0 {
+                    continue;
+                }
+                let area = w1 + w2 + w3;
+                let z = (w1 * z_values[0] + w2 * z_values[1] + w3 * z_values[2]) / area;
+                // Depths are between 0 and 1, where floats sort like their bits.
+                depth.fetch_min(z.to_bits(), Ordering::Relaxed);
+            }
+        });
+}
+
+/// A color that's always the same for the same material name, so materials
+/// can be told apart and recognized between runs. The name is hashed with
+/// 64 bit FNV-1a, which unlike `DefaultHasher` won't change between builds.
+pub fn material_color(name: &str) -> Color {
+    let hash = name.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    hue_color((hash % 360) as f32)
+}
+
+/// Blue for a single depth test pass through green and yellow to red for
+/// `OVERDRAW_MAX` or more.
+fn heat_color(passes: u32) -> Color {
+    let heat = (passes.saturating_sub(1) as f32 / (OVERDRAW_MAX - 1) as f32).min(1.0);
+    hue_color(240.0 * (1.0 - heat))
+}
+
+/// Fully saturated, fully bright color with `hue` in degrees.
+fn hue_color(hue: f32) -> Color {
+    let sector = hue.rem_euclid(360.0) / 60.0;
+    let rising = 1.0 - (sector % 2.0 - 1.0).abs();
+    let (r, g, b) = match sector as u32 {
+        0 => (1.0, rising, 0.0),
+        1 => (rising, 1.0, 0.0),
+        2 => (0.0, 1.0, rising),
+        3 => (0.0, rising, 1.0),
+        4 => (rising, 0.0, 1.0),
+        _ => (1.0, 0.0, rising),
+    };
+    Color { r, g, b, a: 1.0 }
+}
@@ -0,0 +1,78 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Save a `width` by `height` RGBA frame in the working directory as
+/// `screenshot-<date>-<time>.png`, in UTC down to the millisecond so quick
+/// presses don't overwrite each other.
+pub fn save_screenshot(
+    frame: &[u8],
+    width: usize,
+    height: usize,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let path = PathBuf::from(format!("screenshot-{}.png", timestamp(SystemTime::now())));
+    image::save_buffer(
+        &path,
+        frame,
+        width as u32,
+        height as u32,
+        image::ColorType::Rgba8,
+    )?;
+    Ok(path)
+}
+
+/// Shrink a frame `factor` times in both directions by averaging every
+/// `factor` by `factor` block of pixels, which smooths the edges of a frame
+/// drawn that much bigger.
+pub fn downsample(frame: &[u8], width: usize, height: usize, factor: usize) -> Vec<u8> {
+    let (small_width, small_height) = (width / factor, height / factor);
+    let mut small = vec![0u8; small_width * small_height * 4];
+    for y in 0..small_height {
+        for x in 0..small_width {
+            let mut sum = [0u32; 4];
+            for sy in y * factor..(y + 1) * factor {
+                for sx in x * factor..(x + 1) * factor {
+                    let index = (sy * width + sx) * 4;
+                    for channel in 0..4 {
+                        sum[channel] += frame[index + channel] as u32;
+                    }
+                }
+            }
+            let index = (y * small_width + x) * 4;
+            for channel in 0..4 {
+                small[index + channel] = (sum[channel] / (factor * factor) as u32) as u8;
+            }
+        }
+    }
+    small
+}
+
+/// `YYYYMMDD-HHMMSS-mmm` in UTC.
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, day_seconds) = (seconds / 86400, seconds % 86400);
+    // Days to a proleptic Gregorian date, counting in 400 year eras that start
+    // on March 1st so leap days fall at the end.
+    let shifted = days + 719_468;
+    let era = shifted / 146_097;
+    let day_of_era = shifted % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}{:02}{:02}-{:03}",
+        day_seconds / 3600,
+        day_seconds / 60 % 60,
+        day_seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
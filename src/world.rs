@@ -0,0 +1,879 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use image::{DynamicImage, GenericImageView, Pixel};
+use nalgebra::{Matrix3, Matrix4, Perspective3, Point2, Point3, Point4, Vector3};
+use rayon::prelude::*;
+
+use crate::bookmarks::Bookmark;
+use crate::camera::Camera;
+use crate::mesh::bounds::{Aabb, BoundingSphere, Frustum};
+use crate::mesh::bvh::Bvh;
+use crate::mesh::ray::{Facing, Ray};
+use crate::mesh::{Color, Material, Mesh, Normal, TextureCoord, Vertex};
+use crate::render::{
+    DebugFrame, DebugView, MISSING_UV_COLOR, RenderMode, draw_line, fill_depth, material_color,
+};
+
+/// Size in pixels of the window and of frames drawn without one.
+pub const WIDTH: usize = 500;
+pub const HEIGHT: usize = 500;
+
+/// How many pixels of the projected bounding circle one triangle should cover
+/// before it's worth switching to a more detailed level.
+const LOD_PIXELS_PER_TRI: f32 = 4.0;
+
+/// BVH nodes with at most this many triangles are frustum culled as a whole
+/// rather than testing their children.
+const CULL_GROUP_TRIS: usize = 512;
+
+/// Blended over the selected object, and more strongly over the picked triangle.
+const SELECTION_TINT: Color = Color {
+    r: 1.0,
+    g: 0.6,
+    b: 0.0,
+    a: 1.0,
+};
+const SELECTED_TRI_TINT: Color = Color {
+    r: 1.0,
+    g: 0.0,
+    b: 1.0,
+    a: 1.0,
+};
+
+pub struct Object {
+    pub mesh: Box<dyn Mesh>,
+    /// Simplified versions of `mesh`, each coarser than the one before.
+    pub lods: Vec<Box<dyn Mesh>>,
+    /// Hierarchies over the triangles of every level, `mesh` first and then
+    /// `lods`.
+    pub bvhs: Vec<Bvh>,
+    /// Model matrix placing the mesh in the world.
+    pub transform: Matrix4<f32>,
+    /// Model space bounds of `mesh`.
+    pub bounds: BoundingSphere,
+    pub aabb: Aabb,
+}
+
+impl Object {
+    pub fn new(mesh: Box<dyn Mesh>, lods: Vec<Box<dyn Mesh>>, transform: Matrix4<f32>) -> Self {
+        let bounds = mesh.bounding_sphere();
+        let aabb = mesh.aabb();
+        let bvhs = std::iter::once(&mesh)
+            .chain(&lods)
+            .map(|level| Bvh::from_mesh(level.as_ref()))
+            .collect();
+        Object {
+            mesh,
+            lods,
+            bvhs,
+            transform,
+            bounds,
+            aabb,
+        }
+    }
+
+    pub fn world_aabb(&self) -> Aabb {
+        self.aabb.transformed(&self.transform)
+    }
+
+    /// Level `index`, where 0 is the full mesh and higher levels are coarser.
+    pub fn level(&self, index: usize) -> &dyn Mesh {
+        match index {
+            0 => self.mesh.as_ref(),
+            _ => self.lods[index - 1].as_ref(),
+        }
+    }
+
+    /// The coarsest level that still has about one triangle per
+    /// `LOD_PIXELS_PER_TRI` pixels of the object's projected bounding circle
+    /// in a frame `height` pixels high.
+    pub fn lod_for(
+        &self,
+        view_mat: &Matrix4<f32>,
+        proj_mat: &Matrix4<f32>,
+        height: usize,
+    ) -> usize {
+        let bounds = self.bounds.transformed(&(view_mat * self.transform));
+        // The camera looks down -z in view space.
+        let distance = -bounds.center.z;
+        if self.lods.is_empty() || distance <= bounds.radius {
+            return 0;
+        }
+        let radius_px = bounds.radius * proj_mat[(1, 1)] / distance * height as f32 * 0.5;
+        let wanted_tris = std::f32::consts::PI * radius_px * radius_px / LOD_PIXELS_PER_TRI;
+        (1..=self.lods.len())
+            .rev()
+            .find(|index| self.lods[index - 1].tris().len() as f32 >= wanted_tris)
+            .unwrap_or(0)
+    }
+}
+
+struct TriParam {
+    screen_verts: [Point2<f32>; 3],
+    normals: [Vector3<f32>; 3],
+    z_values: [f32; 3],
+    texture_coords: Option<[TextureCoord; 3]>,
+    colors: Option<[Color; 3]>,
+    /// View space tangent and bitangent, only set when the material has a normal map.
+    tangents: Option<[Vector3<f32>; 2]>,
+    w_values: [f32; 3],
+    /// Color blended half and half over the shaded one.
+    highlight: Option<Color>,
+}
+
+pub struct Light {
+    pub position: Point3<f32>,
+    pub target: Point3<f32>,
+    pub ambient: f32,
+}
+
+pub struct World {
+    pub camera: Camera,
+    pub light: Light,
+    pub models: Vec<Object>,
+    pub proj_mat: Matrix4<f32>,
+    /// Hierarchy over the world space bounds of `models`.
+    pub bvh: Bvh,
+    /// Last thing picked with the mouse, highlighted when drawing.
+    pub selection: Option<Pick>,
+    pub render_mode: RenderMode,
+    pub debug_view: Option<DebugView>,
+    /// Size in pixels of the frames `draw` fills.
+    pub width: usize,
+    pub height: usize,
+}
+
+/// What a ray from the camera hit first.
+pub struct Pick {
+    /// Index into `World::models`.
+    pub object: usize,
+    /// Index into the triangles of the object's full detail mesh.
+    pub tri: usize,
+    pub material: String,
+    /// Weights of the triangle's three corners at the hit point.
+    pub barycentric: [f32; 3],
+    /// Hit point in world space.
+    pub position: Point3<f32>,
+}
+
+impl fmt::Display for Pick {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [w1, w2, w3] = self.barycentric;
+        write!(
+            f,
+            "object {} tri {} material {:?} at ({:.4}, {:.4}, {:.4}), barycentric ({w1:.3}, {w2:.3}, {w3:.3})",
+            self.object, self.tri, self.material, self.position.x, self.position.y, self.position.z
+        )
+    }
+}
+
+impl World {
+    pub fn new(camera: Camera, light: Light, proj_mat: Matrix4<f32>, models: Vec<Object>) -> Self {
+        let boxes: Vec<Aabb> = models.iter().map(Object::world_aabb).collect();
+        World {
+            camera,
+            light,
+            models,
+            proj_mat,
+            bvh: Bvh::build(&boxes),
+            selection: None,
+            render_mode: RenderMode::Shaded,
+            debug_view: None,
+            width: WIDTH,
+            height: HEIGHT,
+        }
+    }
+
+    /// Draw `width` by `height` frames from now on, widening or narrowing the
+    /// view to match while keeping its vertical field of view.
+    pub fn set_size(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        let mut projection = Perspective3::from_matrix_unchecked(self.proj_mat);
+        projection.set_aspect(width as f32 / height as f32);
+        self.proj_mat = projection.to_homogeneous();
+    }
+
+    /// Bring `bvh` up to date after object transforms changed.
+    pub fn refit(&mut self) {
+        let boxes: Vec<Aabb> = self.models.iter().map(Object::world_aabb).collect();
+        self.bvh.refit(&boxes);
+    }
+
+    /// Move the scene so its bounding box is centered on the origin and, with
+    /// `normalize`, scale it to fit in a unit sphere.
+    pub fn fit(&mut self, recenter: bool, normalize: bool) {
+        let Some(bounds) = self.bounds() else {
+            return;
+        };
+        let mut adjust = Matrix4::identity();
+        if normalize && bounds.radius > 0.0 {
+            adjust = Matrix4::new_translation(&bounds.center.coords)
+                * Matrix4::new_scaling(1.0 / bounds.radius)
+                * Matrix4::new_translation(&-bounds.center.coords);
+        }
+        if recenter {
+            adjust = Matrix4::new_translation(&-bounds.center.coords) * adjust;
+        }
+        for object in &mut self.models {
+            object.transform = adjust * object.transform;
+        }
+        self.refit();
+    }
+
+    /// Sphere around every object in world space, `None` when there's nothing
+    /// to bound.
+    pub fn bounds(&self) -> Option<BoundingSphere> {
+        bounds_of(&self.models)
+    }
+
+    /// Bounds of the selected object, or the whole scene when nothing is
+    /// selected.
+    pub fn focus(&self) -> Option<BoundingSphere> {
+        match &self.selection {
+            Some(pick) => bounds_of(std::slice::from_ref(&self.models[pick.object])),
+            None => self.bounds(),
+        }
+    }
+
+    /// The current view, to come back to later with `go_to`.
+    pub fn bookmark(&self) -> Bookmark {
+        Bookmark {
+            position: self.camera.position,
+            target: self.camera.target,
+            yaw: self.camera.yaw,
+            pitch: self.camera.pitch,
+            fov: Perspective3::from_matrix_unchecked(self.proj_mat).fovy(),
+        }
+    }
+
+    pub fn go_to(&mut self, bookmark: &Bookmark) {
+        self.camera.position = bookmark.position;
+        self.camera.target = bookmark.target;
+        self.camera.pivot = bookmark.target;
+        self.camera.yaw = bookmark.yaw;
+        self.camera.pitch = bookmark.pitch;
+        self.camera.velocity = Vector3::zeros();
+        let mut projection = Perspective3::from_matrix_unchecked(self.proj_mat);
+        projection.set_fovy(bookmark.fov);
+        self.proj_mat = projection.to_homogeneous();
+    }
+
+    /// View distances of the nearest and furthest points of the scene, no
+    /// nearer than the near plane.
+    pub fn depth_range(&self, view_mat: &Matrix4<f32>) -> [f32; 2] {
+        let near = Perspective3::from_matrix_unchecked(self.proj_mat).znear();
+        let Some(bounds) = self.bounds() else {
+            return [near, near + 1.0];
+        };
+        // The camera looks down -z in view space.
+        let distance = -view_mat.transform_point(&bounds.center).z;
+        let nearest = (distance - bounds.radius).max(near);
+        [nearest, (distance + bounds.radius).max(nearest + near)]
+    }
+
+    /// Point the camera at `focus` so all of it is in view.
+    pub fn frame(&mut self) {
+        let Some(bounds) = self.focus() else {
+            return;
+        };
+        // proj_mat scales x and y by the cotangents of the half fovs.
+        let half_fov = (1.0 / self.proj_mat[(0, 0)].max(self.proj_mat[(1, 1)])).atan();
+        self.camera.frame(&bounds, half_fov);
+    }
+
+    /// World space ray from the camera through pixel (`x`, `y`) of the frame.
+    pub fn ray_through(&self, view_mat: &Matrix4<f32>, x: f32, y: f32) -> Option<Ray> {
+        let inverse = (self.proj_mat * view_mat).try_inverse()?;
+        // Undo the viewport mapping in `draw`, then unproject onto the near
+        // and far planes.
+        let ndc_x = x / self.width as f32 * 2.0 - 1.0;
+        let ndc_y = 1.0 - y / self.height as f32 * 2.0;
+        let near = inverse.transform_point(&Point3::new(ndc_x, ndc_y, -1.0));
+        let far = inverse.transform_point(&Point3::new(ndc_x, ndc_y, 1.0));
+        Some(Ray {
+            origin: near,
+            direction: (far - near).try_normalize(f32::EPSILON)?,
+        })
+    }
+
+    /// Closest triangle facing the camera along `ray`, so back faces `draw`
+    /// skips can't be picked. Always tested against the full detail mesh
+    /// whatever level is being drawn.
+    pub fn pick(&self, ray: &Ray) -> Option<Pick> {
+        let mut closest = None;
+        let (object, distance) = self.bvh.intersect_ray(ray, |index, max| {
+            let object = &self.models[index];
+            // Mirroring turns the front of every triangle into its back.
+            let facing = if object.transform.determinant() < 0.0 {
+                Facing::Back
+            } else {
+                Facing::Front
+            };
+            // Model space distances along the ray match the world space ones.
+            let hit = ray
+                .transformed(&object.transform.try_inverse()?)
+                .intersect_mesh(object.mesh.as_ref(), &object.bvhs[0], facing)?;
+            if hit.distance >= max {
+                return None;
+            }
+            closest = Some(hit);
+            Some(hit.distance)
+        })?;
+        let hit = closest?;
+        Some(Pick {
+            object,
+            tri: hit.tri,
+            material: self.models[object].mesh.tris()[hit.tri].mtl.name.clone(),
+            barycentric: hit.barycentric,
+            position: ray.at(distance),
+        })
+    }
+
+    pub fn draw(&mut self, view_mat: Matrix4<f32>, frame: &mut [u8], flip_normals: bool) {
+        frame.fill(255);
+        let light_dir_world = (self.light.target - self.light.position).normalize();
+        let light_dir_view = (view_mat.fixed_view::<3, 3>(0, 0) * light_dir_world).normalize();
+        let frustum = Frustum::from_matrix(&(self.proj_mat * view_mat));
+        let mut visible_models: Vec<usize> = vec![];
+        self.bvh.cull(
+            |aabb| frustum.intersects_aabb(aabb),
+            1,
+            |models| visible_models.extend_from_slice(models),
+        );
+        // Keep the scene's own order so overlapping draws stay deterministic.
+        visible_models.sort_unstable();
+        let model_with_mats: Vec<(usize, &Object, Matrix4<f32>)> = visible_models
+            .into_iter()
+            .map(|index| (index, &self.models[index]))
+            .filter(|(_, model)| {
+                frustum.intersects_sphere(&model.bounds.transformed(&model.transform))
+            })
+            .map(|(index, model)| (index, model, model.transform))
+            .collect();
+
+        let mut screen_verts: Vec<Point2<f32>> = vec![];
+        let mut zvalues: Vec<f32> = vec![];
+        let mut wvalues: Vec<f32> = vec![];
+        let mut zbuffer: Vec<AtomicU32> = (0..self.width * self.height)
+            .map(|_| AtomicU32::new(f32::to_bits(1.0)))
+            .collect();
+        // Screen space ends of the edges to draw once every triangle is in
+        // the depth buffer, each shared edge only once.
+        let debug = DebugFrame {
+            depth_range: self.depth_range(&view_mat),
+            overdraw: match self.debug_view {
+                Some(DebugView::Overdraw) => (0..self.width * self.height)
+                    .map(|_| AtomicU32::new(0))
+                    .collect(),
+                _ => vec![],
+            },
+        };
+        let mut edges: Vec<[Point3<f32>; 2]> = vec![];
+        let mut seen_edges: HashSet<[usize; 2]> = HashSet::new();
+
+        // Iterate over meshes in sorted zbuffer order
+        for (model_index, model, model_mat) in &model_with_mats {
+            let level = model.lod_for(&view_mat, &self.proj_mat, self.height);
+            let mesh = model.level(level);
+            let selection = self
+                .selection
+                .as_ref()
+                .filter(|pick| pick.object == *model_index);
+            // Picks are on the full mesh, coarser levels have other triangles.
+            let selected_tri = selection.filter(|_| level == 0).map(|pick| pick.tri);
+            let normal_mat = (view_mat * model_mat)
+                .fixed_view::<3, 3>(0, 0)
+                .try_inverse()
+                .unwrap_or_else(Matrix3::identity)
+                .transpose();
+            let model_view = (view_mat * model_mat).fixed_view::<3, 3>(0, 0).into_owned();
+            let proj = self.proj_mat * view_mat * model_mat;
+
+            // The BVH is in model space, and so are the planes of a clip matrix
+            // that includes the model matrix.
+            let model_frustum = Frustum::from_matrix(&proj);
+            let mut visible_tris: Vec<usize> = vec![];
+            model.bvhs[level].cull(
+                |aabb| model_frustum.intersects_aabb(aabb),
+                CULL_GROUP_TRIS,
+                |tris| visible_tris.extend_from_slice(tris),
+            );
+
+            // Only project the vertices visible triangles use.
+            let vert_count = mesh.verts().len();
+            screen_verts.resize(vert_count, Point2::new(f32::NAN, f32::NAN));
+            zvalues.resize(vert_count, 0.0);
+            wvalues.resize(vert_count, 0.0);
+            let mut projected = vec![false; vert_count];
+            for tri in &visible_tris {
+                for index in mesh.tris()[*tri].verts {
+                    if projected[index] {
+                        continue;
+                    }
+                    projected[index] = true;
+                    let vertex = mesh.verts()[index];
+                    let persproj = proj * Point4::new(vertex.x, vertex.y, vertex.z, 1.0);
+                    let ndc_x = persproj.x / persproj.w;
+                    let ndc_y = persproj.y / persproj.w;
+                    let ndc_z = persproj.z / persproj.w;
+                    let ndc_w = 1.0 / persproj.w;
+
+                    if !(0.0..=1.0).contains(&ndc_z) {
+                        screen_verts[index] = Point2::new(f32::NAN, f32::NAN);
+                    } else {
+                        let screen_x = (ndc_x + 1.0) * 0.5 * self.width as f32;
+                        let screen_y = (1.0 - ndc_y) * 0.5 * self.height as f32;
+                        screen_verts[index] = Point2::new(screen_x, screen_y);
+                    }
+                    wvalues[index] = ndc_w;
+                    zvalues[index] = ndc_z;
+                }
+            }
+
+            let transformed_norms: Vec<Normal> = mesh
+                .normals()
+                .iter()
+                .map(|norm| normal_mat * norm)
+                .collect();
+
+            // Draw the triangles
+            for (tri_index, tri) in visible_tris.iter().map(|tri| (*tri, &mesh.tris()[*tri])) {
+                let vert1_index = tri.verts[0];
+                let vert2_index = tri.verts[1];
+                let vert3_index = tri.verts[2];
+                let s1 = screen_verts[vert1_index];
+                let s2 = screen_verts[vert2_index];
+                let s3 = screen_verts[vert3_index];
+                if !should_be_rendered(s1, s2, s3) {
+                    continue;
+                }
+                let front_facing = is_front_facing(s1, s2, s3);
+                if self.render_mode != RenderMode::Shaded
+                    && (front_facing || self.render_mode == RenderMode::Wireframe)
+                {
+                    for (start, end) in [(0, 1), (1, 2), (2, 0)] {
+                        let (a, b) = (tri.verts[start], tri.verts[end]);
+                        if seen_edges.insert([a.min(b), a.max(b)]) {
+                            edges.push([a, b].map(|index| {
+                                let screen = screen_verts[index];
+                                Point3::new(screen.x, screen.y, zvalues[index])
+                            }));
+                        }
+                    }
+                }
+                if !front_facing || self.render_mode == RenderMode::Wireframe {
+                    continue;
+                }
+                if self.render_mode == RenderMode::HiddenLine {
+                    // Only the depth buffer matters for hiding edges.
+                    fill_depth(
+                        [s1, s2, s3],
+                        [vert1_index, vert2_index, vert3_index].map(|index| zvalues[index]),
+                        &zbuffer,
+                        (self.width, self.height),
+                    );
+                    continue;
+                }
+                let texture_coords = mesh.texturecoords();
+
+                let n1_idx = tri.norms[0];
+                let n2_idx = tri.norms[1];
+                let n3_idx = tri.norms[2];
+                let mut n1;
+                let mut n2;
+                let mut n3;
+
+                if n1_idx > 0 && n2_idx > 0 && n3_idx > 0 {
+                    n1 = transformed_norms[n1_idx - 1];
+                    n2 = transformed_norms[n2_idx - 1];
+                    n3 = transformed_norms[n3_idx - 1];
+                } else {
+                    n1 = transformed_norms[vert1_index];
+                    n2 = transformed_norms[vert2_index];
+                    n3 = transformed_norms[vert3_index];
+                }
+
+                if flip_normals {
+                    n1 = -n1;
+                    n2 = -n2;
+                    n3 = -n3;
+                }
+
+                let z1 = zvalues[vert1_index];
+                let z2 = zvalues[vert2_index];
+                let z3 = zvalues[vert3_index];
+
+                let w1 = wvalues[vert1_index];
+                let w2 = wvalues[vert2_index];
+                let w3 = wvalues[vert3_index];
+
+                let t1 = texture_coords.get(tri.texes[0]);
+                let t2 = texture_coords.get(tri.texes[1]);
+                let t3 = texture_coords.get(tri.texes[2]);
+
+                let tangents = match (&tri.mtl.map_norm, t1, t2, t3) {
+                    (Some(_), Some(tc1), Some(tc2), Some(tc3)) => tangent_frame(
+                        [
+                            mesh.verts()[vert1_index],
+                            mesh.verts()[vert2_index],
+                            mesh.verts()[vert3_index],
+                        ],
+                        [*tc1, *tc2, *tc3],
+                    )
+                    .map(|[t, b]| [model_view * t, model_view * b]),
+                    _ => None,
+                };
+
+                let vertex_colors = mesh.colors();
+                let c1 = vertex_colors.get(vert1_index);
+                let c2 = vertex_colors.get(vert2_index);
+                let c3 = vertex_colors.get(vert3_index);
+
+                self.draw_triangle(
+                    TriParam {
+                        screen_verts: [s1, s2, s3],
+                        normals: [n1, n2, n3],
+                        z_values: [z1, z2, z3],
+                        texture_coords: match (t1, t2, t3) {
+                            (Some(tc1), Some(tc2), Some(tc3)) => Some([*tc1, *tc2, *tc3]),
+                            _ => None,
+                        },
+                        colors: match (c1, c2, c3) {
+                            (Some(c1), Some(c2), Some(c3)) => Some([*c1, *c2, *c3]),
+                            _ => None,
+                        },
+                        tangents,
+                        w_values: [w1, w2, w3],
+                        highlight: if selected_tri == Some(tri_index) {
+                            Some(SELECTED_TRI_TINT)
+                        } else {
+                            selection.map(|_| SELECTION_TINT)
+                        },
+                    },
+                    &tri.mtl,
+                    frame,
+                    &mut zbuffer,
+                    light_dir_view,
+                    &debug,
+                );
+            }
+            screen_verts.clear();
+            zvalues.clear();
+            wvalues.clear();
+            seen_edges.clear();
+        }
+
+        if self.debug_view == Some(DebugView::Overdraw) {
+            debug.paint_overdraw(frame);
+        }
+        let depth_test = self.render_mode != RenderMode::Wireframe;
+        for [start, end] in edges {
+            draw_line(
+                start,
+                end,
+                frame,
+                &zbuffer,
+                (self.width, self.height),
+                depth_test,
+            );
+        }
+    }
+
+    fn draw_triangle(
+        &self,
+        tri: TriParam,
+        mtl: &Material,
+        frame: &mut [u8],
+        zbuffer: &mut [AtomicU32],
+        light_dir_view: Vector3<f32>,
+        debug: &DebugFrame,
+    ) {
+        let (x1, y1) = (tri.screen_verts[0].x, tri.screen_verts[0].y);
+        let (x2, y2) = (tri.screen_verts[1].x, tri.screen_verts[1].y);
+        let (x3, y3) = (tri.screen_verts[2].x, tri.screen_verts[2].y);
+        let min_x = (x1.min(x2).min(x3).max(0.0)) as usize;
+        let max_x = (x1.max(x2).max(x3).min(self.width as f32 - 1.0) + 1.0) as usize;
+        let min_y = (y1.min(y2).min(y3).max(0.0)) as usize;
+        let max_y = (y1.max(y2).max(y3).min(self.height as f32 - 1.0) + 1.0) as usize;
+
+        if min_x > max_x || min_y > max_y {
+            return;
+        }
+
+        let z1 = tri.z_values[0];
+        let z2 = tri.z_values[1];
+        let z3 = tri.z_values[2];
+        let perspective_warp_1 = tri.w_values[0];
+        let perspective_warp_2 = tri.w_values[1];
+        let perspective_warp_3 = tri.w_values[2];
+        let n1 = tri.normals[0];
+        let n2 = tri.normals[1];
+        let n3 = tri.normals[2];
+        let ambient = self.light.ambient;
+        let mtl_color = material_color(&mtl.name);
+
+        let edge = |(ax, ay): (f32, f32), (bx, by): (f32, f32), (px, py): (f32, f32)| -> f32 {
+            (py - ay) * (bx - ax) - (px - ax) * (by - ay)
+        };
+
+        let row_stride = self.width * 4;
+
+        frame
+            .par_chunks_exact_mut(row_stride)
+            .skip(min_y)
+            .take(max_y - min_y)
+            .enumerate()
+            .for_each(|(row_idx, row)| {
+                let y = row_idx + min_y;
+                for x in min_x..=max_x {
+                    let p = (x as f32, y as f32);
+                    let mut w1 = edge((x2, y2), (x3, y3), p);
+                    let mut w2 = edge((x3, y3), (x1, y1), p);
+                    let mut w3 = edge((x1, y1), (x2, y2), p);
+                    if w1 < 0.0 || w2 < 0.0 || w3 < 0.0 {
+                        continue;
+                    }
+
+                    let idx = x * 4;
+                    if idx + 4 > row.len() {
+                        continue;
+                    }
+
+                    let z_index = y * self.width + x;
+                    if z_index >= self.width * self.height {
+                        continue;
+                    }
+
+                    let area = w1 + w2 + w3;
+
+                    w1 /= area;
+                    w2 /= area;
+                    w3 /= area;
+
+                    let current_z = &zbuffer[z_index];
+                    let current_z_bits = current_z.load(Ordering::Relaxed);
+                    let interpolated_z = w1 * z1 + w2 * z2 + w3 * z3;
+                    if interpolated_z > f32::from_bits(current_z_bits)
+                        || current_z
+                            .compare_exchange(
+                                current_z_bits,
+                                f32::to_bits(interpolated_z),
+                                Ordering::Relaxed,
+                                Ordering::Relaxed,
+                            )
+                            .is_err()
+                    {
+                        continue;
+                    }
+                    if self.debug_view == Some(DebugView::Overdraw) {
+                        debug.overdraw[z_index].fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+
+                    let mut ka = mtl.ka;
+                    let mut kd = mtl.kd;
+                    let mut ks = mtl.ks;
+                    let mut ke = mtl.ke;
+                    let mut normal_sample = None;
+
+                    let one_over_z =
+                        w1 * perspective_warp_1 + w2 * perspective_warp_2 + w3 * perspective_warp_3;
+
+                    // Vertex colors stand in for the material's base color.
+                    if let Some([c1, c2, c3]) = tri.colors {
+                        let vertex_color = (c1 * (w1 * perspective_warp_1)
+                            + c2 * (w2 * perspective_warp_2)
+                            + c3 * (w3 * perspective_warp_3))
+                            * (1.0 / one_over_z);
+                        ka = vertex_color;
+                        kd = vertex_color;
+                    }
+
+                    let uv = tri.texture_coords.map(|[uv1, uv2, uv3]| {
+                        let u_over_z = w1 * uv1.u * perspective_warp_1
+                            + w2 * uv2.u * perspective_warp_2
+                            + w3 * uv3.u * perspective_warp_3;
+                        let v_over_z = w1 * uv1.v * perspective_warp_1
+                            + w2 * uv2.v * perspective_warp_2
+                            + w3 * uv3.v * perspective_warp_3;
+                        (u_over_z / one_over_z, v_over_z / one_over_z)
+                    });
+                    if let Some((u, v)) = uv {
+                        let interpolated_u = u.clamp(0.0, 1.0);
+                        let interpolated_v = 1.0 - v.clamp(0.0, 1.0);
+
+                        if let Some(ref tex) = mtl.map_ka {
+                            ka = sample_texture(interpolated_u, interpolated_v, tex);
+                        }
+                        if let Some(ref tex) = mtl.map_kd {
+                            kd = sample_texture(interpolated_u, interpolated_v, tex);
+                        }
+                        if let Some(ref tex) = mtl.map_ks {
+                            ks = sample_texture(interpolated_u, interpolated_v, tex);
+                        }
+                        if let Some(ref tex) = mtl.map_ke {
+                            ke = sample_texture(interpolated_u, interpolated_v, tex);
+                        }
+                        if let Some(ref tex) = mtl.map_norm {
+                            normal_sample =
+                                Some(sample_texture(interpolated_u, interpolated_v, tex));
+                        }
+                    }
+                    let nx_over_z = w1 * n1.x * perspective_warp_1
+                        + w2 * n2.x * perspective_warp_2
+                        + w3 * n3.x * perspective_warp_3;
+                    let ny_over_z = w1 * n1.y * perspective_warp_1
+                        + w2 * n2.y * perspective_warp_2
+                        + w3 * n3.y * perspective_warp_3;
+                    let nz_over_z = w1 * n1.z * perspective_warp_1
+                        + w2 * n2.z * perspective_warp_2
+                        + w3 * n3.z * perspective_warp_3;
+
+                    let mut interpolated_normal = Vector3::new(
+                        nx_over_z / one_over_z,
+                        ny_over_z / one_over_z,
+                        nz_over_z / one_over_z,
+                    )
+                    .normalize();
+
+                    if let (Some(sample), Some([tangent, bitangent])) =
+                        (normal_sample, tri.tangents)
+                    {
+                        interpolated_normal =
+                            apply_normal_map(interpolated_normal, tangent, bitangent, sample);
+                    }
+
+                    let diffuse = light_dir_view.dot(&interpolated_normal).clamp(0.1, 1.0);
+                    let specular = 0.0; //no fancy lighting for now its too laggy
+                    let mut color = ka * ambient + kd * diffuse + ks * specular + ke;
+                    color = match self.debug_view {
+                        None | Some(DebugView::Overdraw) => color,
+                        Some(DebugView::Depth) => {
+                            // 1/w interpolates to one over the view distance.
+                            let [near, far] = debug.depth_range;
+                            let shade =
+                                1.0 - ((1.0 / one_over_z - near) / (far - near)).clamp(0.0, 1.0);
+                            Color {
+                                r: shade,
+                                g: shade,
+                                b: shade,
+                                a: 1.0,
+                            }
+                        }
+                        // Lighting wants normals pointing into the surface,
+                        // shown turned around so faces lit the right way
+                        // that face the camera come out blue.
+                        Some(DebugView::Normals) => Color {
+                            r: 0.5 - interpolated_normal.x * 0.5,
+                            g: 0.5 - interpolated_normal.y * 0.5,
+                            b: 0.5 - interpolated_normal.z * 0.5,
+                            a: 1.0,
+                        },
+                        Some(DebugView::Uvs) => uv.map_or(MISSING_UV_COLOR, |(u, v)| Color {
+                            r: u.rem_euclid(1.0),
+                            g: v.rem_euclid(1.0),
+                            b: 0.0,
+                            a: 1.0,
+                        }),
+                        Some(DebugView::Materials) => mtl_color,
+                    };
+                    if let Some(highlight) = tri.highlight {
+                        color = color * 0.5 + highlight * 0.5;
+                    }
+                    // Nothing is blended, so alpha from vertex colors would only
+                    // make screenshots and saved frames partly transparent.
+                    row[idx..idx + 4].copy_from_slice(&[
+                        (color.r * 255.0) as u8,
+                        (color.g * 255.0) as u8,
+                        (color.b * 255.0) as u8,
+                        255,
+                    ]);
+                }
+            });
+    }
+}
+
+/// True if the triangle faces the cam. False, we dont need to draw it.
+#[inline(always)]
+fn is_front_facing(p1: Point2<f32>, p2: Point2<f32>, p3: Point2<f32>) -> bool {
+    (p2.x - p1.x) * (p3.y - p1.y) - (p2.y - p1.y) * (p3.x - p1.x) > 0.0
+}
+
+// TODO fix triangles diappearing
+#[inline(always)]
+fn should_be_rendered(s1: Point2<f32>, s2: Point2<f32>, s3: Point2<f32>) -> bool {
+    s1.x.is_finite() && s2.x.is_finite() && s3.x.is_finite()
+}
+
+/// Model space tangent and bitangent of a triangle, i.e. the directions in which
+/// its u and v texture coordinates grow. None when the UVs are degenerate.
+fn tangent_frame(verts: [Vertex; 3], uvs: [TextureCoord; 3]) -> Option<[Vector3<f32>; 2]> {
+    let edge1 = verts[1] - verts[0];
+    let edge2 = verts[2] - verts[0];
+    let (du1, dv1) = (uvs[1].u - uvs[0].u, uvs[1].v - uvs[0].v);
+    let (du2, dv2) = (uvs[2].u - uvs[0].u, uvs[2].v - uvs[0].v);
+    let det = du1 * dv2 - du2 * dv1;
+    if det.abs() < f32::EPSILON {
+        return None;
+    }
+    let tangent = (edge1 * dv2 - edge2 * dv1) / det;
+    let bitangent = (edge2 * du1 - edge1 * du2) / det;
+    Some([tangent, bitangent])
+}
+
+/// Perturb `normal` by a tangent space normal map texel.
+#[inline(always)]
+fn apply_normal_map(
+    normal: Vector3<f32>,
+    tangent: Vector3<f32>,
+    bitangent: Vector3<f32>,
+    sample: Color,
+) -> Vector3<f32> {
+    // Gram-Schmidt so the frame stays orthonormal around the interpolated normal.
+    let Some(tangent) = (tangent - normal * normal.dot(&tangent)).try_normalize(f32::EPSILON)
+    else {
+        return normal;
+    };
+    // Mirrored UVs flip the handedness of the frame.
+    let handedness = normal.cross(&tangent).dot(&bitangent).signum();
+    let bitangent = normal.cross(&tangent) * handedness;
+    (tangent * (sample.r * 2.0 - 1.0)
+        + bitangent * (sample.g * 2.0 - 1.0)
+        + normal * (sample.b * 2.0 - 1.0))
+        .try_normalize(f32::EPSILON)
+        .unwrap_or(normal)
+}
+
+#[inline(always)]
+fn sample_texture(interp_u: f32, interp_v: f32, tex: &DynamicImage) -> Color {
+    let u = (interp_u * (tex.width() - 1) as f32).round() as u32;
+    let v = (interp_v * (tex.height() - 1) as f32).round() as u32;
+    let pixel = tex
+        .get_pixel(u.min(tex.width() - 1), v.min(tex.height() - 1))
+        .to_rgb();
+    Color {
+        r: pixel[0] as f32 / 255.0,
+        g: pixel[1] as f32 / 255.0,
+        b: pixel[2] as f32 / 255.0,
+        a: 1.0,
+    }
+}
+
+/// World space bounding sphere of `objects`, centered on their combined
+/// bounding box.
+pub fn bounds_of(objects: &[Object]) -> Option<BoundingSphere> {
+    let aabb = objects.iter().fold(Aabb::empty(), |aabb, object| {
+        aabb.union(&object.world_aabb())
+    });
+    if aabb.is_empty() {
+        return None;
+    }
+    let spheres: Vec<BoundingSphere> = objects
+        .iter()
+        .map(|object| object.bounds.transformed(&object.transform))
+        .collect();
+    Some(BoundingSphere::enclosing(aabb.center(), &spheres))
+}